@@ -0,0 +1,252 @@
+use crate::postings::FileIds;
+use crate::reader::Grams;
+use crate::FileId;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Rough number of bytes a posting list takes in memory, besides its ids.
+const POSTING_OVERHEAD: usize = 64;
+
+/// Posting lists are ordered by their n-gram table first, then by n-gram.
+type Key = (Grams, String);
+
+/// IndexBuilder collects (n-gram, file) pairs and turns them into posting
+/// lists. Pairs are kept in memory up to half of the memory budget, then
+/// spilled into a sorted run on disk, the other half is left to the batches
+/// of n-grams feeding the builder. Runs are merged once all files were added.
+pub(crate) struct IndexBuilder {
+    dir: PathBuf,
+    memory_budget: usize,
+    postings: BTreeMap<Key, Vec<FileId>>,
+    size: usize,
+    runs: Vec<PathBuf>,
+}
+
+impl IndexBuilder {
+    /// Creates a builder that spills its runs into `dir`, which is removed
+    /// again once the runs are merged.
+    pub fn new(dir: impl AsRef<Path>, memory_budget: usize) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            memory_budget,
+            postings: BTreeMap::new(),
+            size: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Returns the share of the memory budget left to the batches of n-grams
+    /// collected before they are added to the builder.
+    pub fn batch_budget(&self) -> usize {
+        self.memory_budget - self.memory_budget / 2
+    }
+
+    /// Adds the files to the posting list of the n-gram.
+    pub fn add(&mut self, grams: Grams, ngram: &str, ids: &[FileId]) -> io::Result<()> {
+        let postings = match self.postings.get_mut(&(grams, ngram.to_string())) {
+            Some(postings) => postings,
+            None => {
+                self.size += ngram.len() + POSTING_OVERHEAD;
+                self.postings.entry((grams, ngram.to_string())).or_default()
+            }
+        };
+        postings.extend_from_slice(ids);
+        self.size += std::mem::size_of_val(ids);
+
+        if self.size > self.memory_budget / 2 {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /*
+    Run layout, ordered by n-gram table and n-gram:
+    [table][ngram len][ngram][postings len][postings]...
+    <1 byte><-1 byte-><-len-><--4 byte----><--len--->
+    Postings are encoded the same way as in the n-gram tables.
+    */
+    fn spill(&mut self) -> io::Result<()> {
+        if self.postings.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("run_{}", self.runs.len()));
+        log::info!(
+            "spilling {} posting lists into {:?}",
+            self.postings.len(),
+            path
+        );
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for ((grams, ngram), mut ids) in std::mem::take(&mut self.postings) {
+            ids.sort_unstable();
+            ids.dedup();
+            let bytes: Vec<u8> = FileIds::from_iter(ids).into();
+            writer.write_all(&[grams as u8, ngram.len() as u8])?;
+            writer.write_all(ngram.as_bytes())?;
+            writer.write_all(&u32::to_le_bytes(bytes.len() as u32))?;
+            writer.write_all(&bytes)?;
+        }
+        writer.flush()?;
+
+        self.runs.push(path);
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Passes the posting list of every n-gram to `emit`, in the order of the
+    /// n-gram tables and n-grams. File ids are sorted and deduplicated.
+    pub fn finish(
+        mut self,
+        mut emit: impl FnMut(Grams, &str, Vec<FileId>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.runs.is_empty() {
+            for ((grams, ngram), mut ids) in std::mem::take(&mut self.postings) {
+                ids.sort_unstable();
+                ids.dedup();
+                emit(grams, &ngram, ids)?;
+            }
+            return Ok(());
+        }
+
+        self.spill()?;
+        let mut runs = self
+            .runs
+            .iter()
+            .map(|path| Ok(BufReader::new(File::open(path)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // Runs are sorted, so the smallest heads of all runs make up the
+        // next posting list.
+        let mut heads = BinaryHeap::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some((key, ids)) = read_posting(run)? {
+                heads.push(Reverse((key, i, ids)));
+            }
+        }
+        while let Some(Reverse((key, i, mut ids))) = heads.pop() {
+            if let Some(next) = read_posting(&mut runs[i])? {
+                heads.push(Reverse((next.0, i, next.1)));
+            }
+            while heads
+                .peek()
+                .is_some_and(|Reverse((next, _, _))| *next == key)
+            {
+                let Reverse((_, j, more)) = heads.pop().unwrap();
+                ids.extend(more);
+                if let Some(next) = read_posting(&mut runs[j])? {
+                    heads.push(Reverse((next.0, j, next.1)));
+                }
+            }
+            ids.sort_unstable();
+            ids.dedup();
+            emit(key.0, &key.1, ids)?;
+        }
+
+        for path in self.runs.iter() {
+            fs::remove_file(path)?;
+        }
+        fs::remove_dir(&self.dir)
+    }
+}
+
+/// Reads the next posting list of the run, if there is any left.
+fn read_posting(run: &mut impl Read) -> io::Result<Option<(Key, Vec<FileId>)>> {
+    let mut header = [0; 2];
+    match run.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let grams = match header[0] {
+        0 => Grams::Content,
+        1 => Grams::Path,
+        2 => Grams::Symbol,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown n-gram table",
+            ))
+        }
+    };
+    let mut ngram = vec![0; header[1] as usize];
+    run.read_exact(&mut ngram)?;
+    let ngram =
+        String::from_utf8(ngram).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let mut len = [0; 4];
+    run.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+    run.read_exact(&mut bytes)?;
+    let ids = FileIds::from_bytes(&bytes)?.into_iter().collect();
+    Ok(Some(((grams, ngram), ids)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::*;
+    use crate::testutil::Fixture;
+
+    fn build(builder: IndexBuilder) -> Vec<(Grams, String, Vec<u32>)> {
+        let mut postings = Vec::new();
+        builder
+            .finish(|grams, ngram, ids| {
+                postings.push((
+                    grams,
+                    ngram.to_string(),
+                    ids.iter().map(|id| id.0).collect(),
+                ));
+                Ok(())
+            })
+            .unwrap();
+        postings
+    }
+
+    #[test]
+    fn test_spill_and_merge() {
+        let fixture = Fixture::new();
+        let dir = fixture.path("runs");
+
+        let add_all = |builder: &mut IndexBuilder| {
+            for id in (0..100).rev() {
+                let ids = [FileId(id)];
+                builder.add(Grams::Content, "abc", &ids).unwrap();
+                if id % 3 == 0 {
+                    builder.add(Grams::Content, "xyz", &ids).unwrap();
+                    builder.add(Grams::Path, "abc", &ids).unwrap();
+                }
+            }
+            builder.add(Grams::Content, "abc", &[FileId(7)]).unwrap();
+        };
+
+        let mut in_memory = IndexBuilder::new(&dir, usize::MAX);
+        add_all(&mut in_memory);
+        assert!(in_memory.runs.is_empty());
+
+        let mut spilled = IndexBuilder::new(&dir, 256);
+        add_all(&mut spilled);
+        assert!(spilled.runs.len() > 1);
+        // Half of the budget is left to the batches feeding the builder.
+        assert_eq!(spilled.batch_budget(), 128);
+        assert!(spilled.size <= 128);
+
+        let expected = build(in_memory);
+        assert_eq!(build(spilled), expected);
+        assert_eq!(
+            expected
+                .iter()
+                .map(|(grams, ngram, ids)| (*grams, ngram.as_str(), ids.len()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Grams::Content, "abc", 100),
+                (Grams::Content, "xyz", 34),
+                (Grams::Path, "abc", 34)
+            ]
+        );
+        assert_eq!(expected[1].2[..3], [0, 3, 6]);
+        assert!(!Path::new(&dir).exists());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::FileId;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+/// DocIds hands out dense file ids in the order the paths are first seen and
+/// remembers the assignment, so the same path keeps its id across runs.
+#[derive(Default)]
+pub(crate) struct DocIds {
+    ids: BTreeMap<String, FileId>,
+    /// Paths indexed by their file id.
+    paths: Vec<String>,
+}
+
+impl DocIds {
+    const FILE_NAME: &'static str = "doc_ids";
+
+    /// Loads the path table from the index directory; a missing table is empty.
+    pub fn load(dir: &str) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        match File::open(Path::new(dir).join(Self::FILE_NAME)) {
+            Ok(mut file) => file.read_to_end(&mut bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+
+        let mut doc_ids = Self::default();
+        let mut rest = bytes.as_slice();
+        while rest.len() >= 4 {
+            let len = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let path = rest
+                .get(4..4 + len)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated doc ids"))?;
+            let path = String::from_utf8(path.to_vec())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            doc_ids.get_or_insert(&path);
+            rest = &rest[4 + len..];
+        }
+        Ok(doc_ids)
+    }
+
+    /*
+    Doc ids layout, ordered by file id:
+    [path len][path]...
+    <-4 byte-><-len->
+    */
+    pub fn flush(&self, dir: &str) -> io::Result<()> {
+        let dir = Path::new(dir);
+        let tmp = dir.join(format!("tmp_{}", Self::FILE_NAME));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for path in self.paths.iter() {
+            writer.write_all(&u32::to_le_bytes(path.len() as u32))?;
+            writer.write_all(path.as_bytes())?;
+        }
+        writer.flush()?;
+        fs::rename(tmp, dir.join(Self::FILE_NAME))
+    }
+
+    /// Returns the path the id was handed out for.
+    pub fn path(&self, file_id: &FileId) -> Option<&str> {
+        self.paths.get(file_id.0 as usize).map(String::as_str)
+    }
+
+    /// Returns id of the path, if it was handed out.
+    pub fn get(&self, path: &str) -> Option<FileId> {
+        self.ids.get(path).cloned()
+    }
+
+    /// Returns id of the path, allocating the next free one for new paths.
+    pub fn get_or_insert(&mut self, path: &str) -> FileId {
+        if let Some(id) = self.ids.get(path) {
+            return id.clone();
+        }
+        let id = FileId(self.paths.len() as u32);
+        self.ids.insert(path.to_string(), id.clone());
+        self.paths.push(path.to_string());
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::docid::*;
+    use crate::testutil::Fixture;
+
+    #[test]
+    fn test_doc_ids() {
+        let fixture = Fixture::new();
+        let dir = fixture.path("");
+
+        let mut doc_ids = DocIds::default();
+        assert_eq!(doc_ids.get_or_insert("src/main.rs"), FileId(0));
+        assert_eq!(doc_ids.get_or_insert("src/lib.rs"), FileId(1));
+        assert_eq!(doc_ids.get_or_insert("src/main.rs"), FileId(0));
+        doc_ids.flush(&dir).unwrap();
+
+        let mut doc_ids = DocIds::load(&dir).unwrap();
+        assert_eq!(doc_ids.get_or_insert("src/lib.rs"), FileId(1));
+        assert_eq!(doc_ids.get_or_insert("README.md"), FileId(2));
+    }
+}
@@ -0,0 +1,78 @@
+/// Number of bytes at the start of a file sniffed to tell binary from text.
+const SNIFF_LEN: usize = 8 << 10;
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+
+/// Decodes the file content into UTF-8, returning `None` for binary files.
+/// UTF-16 is recognized by its byte order mark, anything else that isn't
+/// valid UTF-8 is read as Latin-1.
+pub(crate) fn decode(bytes: &[u8]) -> Option<String> {
+    if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+        return Some(decode_utf16(rest, u16::from_le_bytes));
+    }
+    if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+        return Some(decode_utf16(rest, u16::from_be_bytes));
+    }
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+    if is_binary(&bytes[..bytes.len().min(SNIFF_LEN)]) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some(text.to_string()),
+        // Latin-1 maps every byte to the code point of the same value.
+        Err(_) => Some(bytes.iter().map(|&b| char::from(b)).collect()),
+    }
+}
+
+/// Text files don't contain NUL bytes and only a few control characters.
+fn is_binary(sniff: &[u8]) -> bool {
+    if sniff.contains(&0) {
+        return true;
+    }
+    let controls = sniff
+        .iter()
+        .filter(|&&b| (b < 0x20 && !b"\t\n\r\x0c\x1b\x08".contains(&b)) || b == 0x7f)
+        .count();
+    controls * 10 > sniff.len()
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| from_bytes([unit[0], unit[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"fn main() {}\n").as_deref(), Some("fn main() {}\n"));
+        assert_eq!(decode("caf\u{e9}".as_bytes()).as_deref(), Some("caf\u{e9}"));
+        assert_eq!(decode(b"\xef\xbb\xbfbom").as_deref(), Some("bom"));
+
+        // Latin-1
+        assert_eq!(
+            decode(b"caf\xe9 cr\xe8me").as_deref(),
+            Some("caf\u{e9} cr\u{e8}me")
+        );
+
+        // UTF-16 with byte order marks
+        assert_eq!(
+            decode(b"\xff\xfeh\x00i\x00\xe9\x00").as_deref(),
+            Some("hi\u{e9}")
+        );
+        assert_eq!(decode(b"\xfe\xff\x00h\x00i").as_deref(), Some("hi"));
+
+        // Binaries
+        assert_eq!(decode(b"\x7fELF\x02\x01\x01\x00\x00"), None);
+        assert_eq!(decode(b"\x01\x02\x03\x04abc"), None);
+        assert_eq!(decode(b""), Some(String::new()));
+    }
+}
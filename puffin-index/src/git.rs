@@ -0,0 +1,270 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Output, Stdio};
+
+/// Entry is a file in the tree of a commit.
+pub(crate) struct Entry {
+    /// Path relative to the indexed directory.
+    pub path: String,
+    /// Object id of the blob holding the content of the file.
+    pub blob: String,
+    pub size: u64,
+}
+
+/// Commit is a commit of the history, along with the lines it changed.
+pub(crate) struct Commit {
+    pub id: String,
+    /// Name and email of the author, as in `Jane Doe <jane@example.com>`.
+    pub author: String,
+    /// Author date in seconds since the Unix epoch.
+    pub time: i64,
+    pub message: String,
+    /// Changes of every file, except binaries.
+    pub diffs: Vec<Diff>,
+}
+
+/// Diff holds the lines a commit changed in a file.
+pub(crate) struct Diff {
+    /// Path relative to the indexed directory, the old one for deleted
+    /// files.
+    pub path: String,
+    /// Added lines prefixed with `+`, removed lines prefixed with `-`, in the
+    /// order of the diff.
+    pub lines: String,
+}
+
+/// Returns a git command running in the repository.
+fn git(repo: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(repo);
+    command
+}
+
+/// Runs the git command and returns its output.
+fn output(command: &mut Command) -> io::Result<Vec<u8>> {
+    check(command.stderr(Stdio::piped()).output()?)
+}
+
+/// Returns the output of the finished git command, or an error carrying its
+/// message if it failed.
+fn check(output: Output) -> io::Result<Vec<u8>> {
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            message.trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Returns the id of the commit the revision, such as a branch name, points to.
+pub(crate) fn resolve(repo: &Path, revision: &str) -> io::Result<String> {
+    let commit = output(git(repo).args([
+        "rev-parse",
+        "--verify",
+        "--end-of-options",
+        &format!("{}^{{commit}}", revision),
+    ]))
+    .map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))?;
+    Ok(String::from_utf8_lossy(&commit).trim().to_string())
+}
+
+/// Lists the files of the commit, sorted by path. Submodules and symbolic
+/// links aren't files and are left out. Only the files under `repo` are
+/// listed if it is a subdirectory of the repository, paths are relative to it.
+pub(crate) fn list_files(repo: &Path, commit: &str) -> io::Result<Vec<Entry>> {
+    let listing = output(git(repo).args(["ls-tree", "-r", "-z", "-l", commit]))?;
+
+    // Records look like `<mode> <type> <object> <size>\t<path>\0`.
+    let mut entries = Vec::new();
+    for record in listing.split(|&b| b == 0).filter(|r| !r.is_empty()) {
+        let record = std::str::from_utf8(record).map_err(invalid_data)?;
+        let (info, path) = record
+            .split_once('\t')
+            .ok_or_else(|| invalid_data("malformed tree entry"))?;
+        let mut info = info.split_ascii_whitespace();
+        let (Some(mode), Some(kind), Some(blob), Some(size)) =
+            (info.next(), info.next(), info.next(), info.next())
+        else {
+            return Err(invalid_data("malformed tree entry"));
+        };
+        if kind != "blob" || mode == "120000" {
+            continue;
+        }
+        entries.push(Entry {
+            path: path.to_string(),
+            blob: blob.to_string(),
+            size: size.parse().map_err(invalid_data)?,
+        });
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Returns ids of the commits reachable from the commit, newest first.
+pub(crate) fn rev_list(repo: &Path, commit: &str) -> io::Result<Vec<String>> {
+    let ids = output(git(repo).args(["rev-list", commit]))?;
+    Ok(String::from_utf8_lossy(&ids)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reads the commits along with their diffs, in the given order. Only the
+/// changes under `repo` are read if it is a subdirectory of the repository,
+/// paths are relative to it.
+pub(crate) fn log(repo: &Path, ids: &[String]) -> io::Result<Vec<Commit>> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    // Every commit starts with a NUL separated header, followed by its patch.
+    // Git reads all of the ids before writing anything, so writing them
+    // upfront can't deadlock.
+    let mut child = git(repo)
+        .args([
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--no-walk=unsorted",
+            "--stdin",
+            "--patch",
+            "--unified=0",
+            "--no-color",
+            "--no-ext-diff",
+            "--no-textconv",
+            "--no-renames",
+            "--relative",
+            "--format=%x00%H%x00%an <%ae>%x00%at%x00%B%x00",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    for id in ids {
+        writeln!(stdin, "{}", id)?;
+    }
+    drop(stdin);
+    let output = check(child.wait_with_output()?)?;
+
+    let log = String::from_utf8_lossy(&output);
+    let mut fields = log.split('\0').skip(1);
+    let mut commits = Vec::new();
+    while let Some(id) = fields.next() {
+        let (Some(author), Some(time), Some(message), Some(patch)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid_data("malformed log entry"));
+        };
+        commits.push(Commit {
+            id: id.to_string(),
+            author: author.to_string(),
+            time: time.parse().map_err(invalid_data)?,
+            message: message.trim_end().to_string(),
+            diffs: parse_patch(patch),
+        });
+    }
+    Ok(commits)
+}
+
+/// Collects the changed lines of every file out of a patch without context
+/// lines.
+fn parse_patch(patch: &str) -> Vec<Diff> {
+    let mut diffs: Vec<Diff> = Vec::new();
+    let mut in_hunk = false;
+    for line in patch.lines() {
+        if line.starts_with("diff --git ") {
+            diffs.push(Diff {
+                path: String::new(),
+                lines: String::new(),
+            });
+            in_hunk = false;
+            continue;
+        }
+        let Some(diff) = diffs.last_mut() else {
+            continue;
+        };
+        if in_hunk {
+            // Anything else is a hunk header or a `\ No newline` marker.
+            if line.starts_with(['+', '-']) {
+                diff.lines.push_str(line);
+                diff.lines.push('\n');
+            }
+        } else if line.starts_with("@@") {
+            in_hunk = true;
+        } else if let Some(path) = line
+            .strip_prefix("--- a/")
+            .or_else(|| line.strip_prefix("+++ b/"))
+        {
+            // Git ends names containing spaces with a tab.
+            diff.path = path.trim_end_matches('\t').to_string();
+        }
+    }
+    // Binaries have no hunks, quoted names aren't worth decoding.
+    diffs.retain(|diff| !diff.path.is_empty() && !diff.lines.is_empty());
+    diffs
+}
+
+/// BlobReader reads blobs out of the object database through a single
+/// long-running `git cat-file --batch`.
+pub(crate) struct BlobReader {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl BlobReader {
+    pub fn new(repo: &Path) -> io::Result<Self> {
+        let mut child = git(repo)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Reads the content of the blob into `contents`.
+    pub fn read(&mut self, blob: &str, contents: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(self.stdin, "{}", blob)?;
+        self.stdin.flush()?;
+
+        // The content follows a `<object> <type> <size>` header line, or the
+        // header is `<object> missing`.
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let size = match header.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse::<usize>().map_err(invalid_data)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no blob {}", blob),
+                ))
+            }
+        };
+        contents.clear();
+        contents.resize(size, 0);
+        self.stdout.read_exact(contents)?;
+        let mut newline = [0u8];
+        self.stdout.read_exact(&mut newline)?;
+        Ok(())
+    }
+}
+
+impl Drop for BlobReader {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,170 @@
+use crate::search::{self, file::Kind};
+use crate::{git, lang, remove_named, FileId};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeBounds;
+use std::path::Path;
+
+/// History keeps track of the documents of the commit history, by type,
+/// author and date. Files of directories and branches aren't part of it.
+#[derive(Default)]
+pub(crate) struct History {
+    commits: BTreeSet<FileId>,
+    diffs: BTreeSet<FileId>,
+    /// Documents of every author, by name and email.
+    authors: BTreeMap<String, BTreeSet<FileId>>,
+    /// Author date of every document.
+    times: BTreeMap<FileId, i64>,
+}
+
+impl History {
+    pub fn add(&mut self, file_id: &FileId, file: &search::File) {
+        let Some(commit) = file.commit.as_ref() else {
+            return;
+        };
+        match file.kind() {
+            Kind::Commit => self.commits.insert(file_id.clone()),
+            Kind::Diff => self.diffs.insert(file_id.clone()),
+            Kind::File => return,
+        };
+        self.authors
+            .entry(commit.author.clone())
+            .or_default()
+            .insert(file_id.clone());
+        self.times.insert(file_id.clone(), commit.time);
+    }
+
+    pub fn remove(&mut self, file_id: &FileId, file: &search::File) {
+        if let Some(commit) = file.commit.as_ref() {
+            remove_named(&mut self.authors, &commit.author, file_id);
+        }
+        self.commits.remove(file_id);
+        self.diffs.remove(file_id);
+        self.times.remove(file_id);
+    }
+
+    /// Whether the document is part of the history.
+    pub fn contains(&self, file_id: &FileId) -> bool {
+        self.times.contains_key(file_id)
+    }
+
+    /// Returns ids of the commit messages.
+    pub fn commits(&self) -> &BTreeSet<FileId> {
+        &self.commits
+    }
+
+    /// Returns ids of the diffs.
+    pub fn diffs(&self) -> &BTreeSet<FileId> {
+        &self.diffs
+    }
+
+    /// Returns ids of the documents of every author, by name and email.
+    pub fn authors(&self) -> &BTreeMap<String, BTreeSet<FileId>> {
+        &self.authors
+    }
+
+    /// Returns ids of the documents authored within the range of times, in
+    /// ascending order.
+    pub fn dated(&self, range: impl RangeBounds<i64>) -> Vec<FileId> {
+        self.times
+            .iter()
+            .filter(|(_, time)| range.contains(time))
+            .map(|(file_id, _)| file_id.clone())
+            .collect()
+    }
+}
+
+/// Turns the commit of the repository rooted at `root` into documents, along
+/// with the key of their doc id: one holding the message of the commit, named
+/// after it, and one per changed file holding the changed lines, named after
+/// the file.
+pub(crate) fn documents(root: &Path, commit: &git::Commit) -> Vec<(String, search::File)> {
+    let meta = search::Commit {
+        id: commit.id.clone(),
+        author: commit.author.clone(),
+        time: commit.time,
+        subject: commit
+            .message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    };
+    let message = format!("{}\n", commit.message);
+    let mut documents = vec![(
+        format!("{}@{}", root.to_str().unwrap(), commit.id),
+        search::File {
+            filename: commit.id.clone(),
+            size: message.len() as u64,
+            content: message,
+            file_type: "unknown".into(),
+            kind: Kind::Commit.into(),
+            commit: Some(meta.clone()),
+            ..Default::default()
+        },
+    )];
+    for diff in commit.diffs.iter() {
+        let path = root.join(&diff.path);
+        let language = lang::detect(&path, &diff.lines);
+        documents.push((
+            format!("{}@{}", path.to_str().unwrap(), commit.id),
+            search::File {
+                filename: path.to_str().unwrap().to_string(),
+                content: diff.lines.clone(),
+                file_type: language.map_or("unknown", |l| l.name).into(),
+                size: diff.lines.len() as u64,
+                kind: Kind::Diff.into(),
+                commit: Some(meta.clone()),
+                ..Default::default()
+            },
+        ));
+    }
+    documents
+}
+
+/// Parses a `YYYY-MM-DD` date into the time its day starts at, in seconds
+/// since the Unix epoch in UTC.
+pub(crate) fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || day < 1 {
+        return None;
+    }
+    let next_month = match month {
+        12 => days_from_civil(year + 1, 1, 1),
+        _ => days_from_civil(year, month + 1, 1),
+    };
+    let days = days_from_civil(year, month, day);
+    (days < next_month).then_some(days * 86400)
+}
+
+/// Returns the number of days since the Unix epoch of a date of the
+/// proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Years start in March, so that the leap day ends them.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(951868800));
+        assert_eq!(parse_date("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-01-00"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+}
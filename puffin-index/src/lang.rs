@@ -0,0 +1,281 @@
+use regex::Regex;
+use std::path::Path;
+
+/// Number of lines at the start and at the end of a file searched for modelines.
+const MODELINE_LINES: usize = 5;
+
+/// Language describes a programming language and how to recognize its files.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Language {
+    /// Canonical name of the language, stored as `search::File.file_type`.
+    pub name: &'static str,
+    aliases: &'static [&'static str],
+    extensions: &'static [&'static str],
+    filenames: &'static [&'static str],
+    interpreters: &'static [&'static str],
+}
+
+impl Language {
+    const fn new(
+        name: &'static str,
+        aliases: &'static [&'static str],
+        extensions: &'static [&'static str],
+        filenames: &'static [&'static str],
+        interpreters: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            aliases,
+            extensions,
+            filenames,
+            interpreters,
+        }
+    }
+}
+
+// Fields: name, aliases, extensions, filenames and interpreters.
+// Loosely based on https://github.com/github-linguist/linguist/blob/master/lib/linguist/languages.yml.
+const LANGUAGES: &[Language] = &[
+    Language::new("C", &[], &["c", "h"], &[], &[]),
+    Language::new("C#", &["csharp", "cs"], &["cs", "csx"], &[], &[]),
+    Language::new(
+        "C++",
+        &["cpp"],
+        &["cc", "cpp", "cxx", "c++", "hh", "hpp", "hxx", "inl"],
+        &[],
+        &[],
+    ),
+    Language::new("CMake", &[], &["cmake"], &["CMakeLists.txt"], &[]),
+    Language::new("CSS", &[], &["css"], &[], &[]),
+    Language::new(
+        "Clojure",
+        &["clj"],
+        &["clj", "cljs", "cljc", "edn"],
+        &[],
+        &[],
+    ),
+    Language::new("Dart", &[], &["dart"], &[], &[]),
+    Language::new(
+        "Dockerfile",
+        &["docker", "containerfile"],
+        &["dockerfile"],
+        &["Dockerfile", "Containerfile"],
+        &[],
+    ),
+    Language::new("Elixir", &["ex"], &["ex", "exs"], &[], &["elixir"]),
+    Language::new(
+        "Erlang",
+        &["erl"],
+        &["erl", "hrl"],
+        &["rebar.config"],
+        &["escript"],
+    ),
+    Language::new("Go", &["golang"], &["go"], &[], &[]),
+    Language::new(
+        "Go Module",
+        &["go.mod", "gomod"],
+        &[],
+        &["go.mod", "go.sum"],
+        &[],
+    ),
+    Language::new(
+        "Groovy",
+        &[],
+        &["groovy", "gradle"],
+        &["Jenkinsfile"],
+        &["groovy"],
+    ),
+    Language::new("HTML", &["xhtml"], &["html", "htm", "xhtml"], &[], &[]),
+    Language::new("Haskell", &["hs"], &["hs", "lhs"], &[], &["runhaskell"]),
+    Language::new("JSON", &[], &["json"], &[], &[]),
+    Language::new("Java", &[], &["java"], &[], &[]),
+    Language::new(
+        "JavaScript",
+        &["js", "node"],
+        &["js", "cjs", "mjs", "jsx"],
+        &[],
+        &["node", "nodejs"],
+    ),
+    Language::new("Kotlin", &[], &["kt", "kts"], &[], &[]),
+    Language::new("Lua", &[], &["lua"], &[], &["lua"]),
+    Language::new(
+        "Makefile",
+        &["make", "mf", "bsdmake"],
+        &["mk", "mak"],
+        &["Makefile", "makefile", "GNUmakefile"],
+        &["make"],
+    ),
+    Language::new("Markdown", &["md"], &["md", "markdown"], &[], &[]),
+    Language::new("OCaml", &[], &["ml", "mli"], &[], &["ocaml"]),
+    Language::new("PHP", &[], &["php"], &[], &["php"]),
+    Language::new("Perl", &["pl"], &["pl", "pm"], &[], &["perl"]),
+    Language::new(
+        "Protocol Buffer",
+        &["protobuf", "proto"],
+        &["proto"],
+        &[],
+        &[],
+    ),
+    Language::new(
+        "Python",
+        &["py", "python3"],
+        &["py", "pyi", "pyw"],
+        &["SConstruct", "SConscript"],
+        &["python"],
+    ),
+    Language::new("R", &[], &["r"], &[], &["Rscript"]),
+    Language::new(
+        "Ruby",
+        &["rb"],
+        &["rb", "rake", "gemspec"],
+        &["Gemfile", "Rakefile"],
+        &["ruby"],
+    ),
+    Language::new("Rust", &["rs"], &["rs"], &[], &[]),
+    Language::new("SQL", &[], &["sql"], &[], &[]),
+    Language::new("Scala", &[], &["scala", "sc"], &[], &["scala"]),
+    Language::new(
+        "Shell",
+        &["sh", "bash", "zsh", "shell-script"],
+        &["sh", "bash", "zsh"],
+        &[".bashrc", ".zshrc", ".profile"],
+        &["sh", "bash", "zsh", "dash", "ksh"],
+    ),
+    Language::new(
+        "Starlark",
+        &["bazel", "bzl"],
+        &["bzl", "star"],
+        &["BUILD", "BUILD.bazel", "WORKSPACE"],
+        &[],
+    ),
+    Language::new("Swift", &[], &["swift"], &[], &[]),
+    Language::new("TOML", &[], &["toml"], &["Cargo.lock"], &[]),
+    Language::new(
+        "TypeScript",
+        &["ts"],
+        &["ts", "cts", "mts", "tsx"],
+        &[],
+        &["deno", "ts-node"],
+    ),
+    Language::new("XML", &[], &["xml", "xsd", "xsl", "svg"], &[], &[]),
+    Language::new("YAML", &["yml"], &["yml", "yaml"], &[], &[]),
+];
+
+lazy_static::lazy_static! {
+    static ref VIM_MODELINE: Regex =
+        Regex::new(r"(?:^|\s)(?:vi|vim|ex)(?:[<=>]?\d+)?:.*?\b(?:ft|filetype|syntax)=([\w+#.-]+)").unwrap();
+    static ref EMACS_MODELINE: Regex = Regex::new(r"-\*-(.*?)-\*-").unwrap();
+}
+
+/// Finds the language by its name or one of its aliases, ignoring case.
+pub fn find(name: &str) -> Option<&'static Language> {
+    let name = name.trim().to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|l| l.name.to_lowercase() == name || l.aliases.contains(&name.as_str()))
+}
+
+/// Detects the language of a file. Explicit editor modelines take precedence,
+/// followed by well-known filenames, shebang lines and finally extensions.
+pub fn detect(path: &Path, content: &str) -> Option<&'static Language> {
+    by_modeline(content)
+        .or_else(|| by_filename(path))
+        .or_else(|| by_shebang(content))
+        .or_else(|| by_extension(path))
+}
+
+fn by_filename(path: &Path) -> Option<&'static Language> {
+    let filename = path.file_name()?.to_str()?;
+    LANGUAGES.iter().find(|l| l.filenames.contains(&filename))
+}
+
+fn by_extension(path: &Path) -> Option<&'static Language> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|l| l.extensions.contains(&extension.as_str()))
+}
+
+fn by_shebang(content: &str) -> Option<&'static Language> {
+    let line = content.lines().next()?.strip_prefix("#!")?;
+    let mut args = line.split_whitespace();
+    let mut interpreter = args.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = args.find(|arg| !arg.starts_with('-') && !arg.contains('='))?;
+    }
+    // python3.11 -> python
+    let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    LANGUAGES
+        .iter()
+        .find(|l| l.interpreters.contains(&interpreter))
+}
+
+fn by_modeline(content: &str) -> Option<&'static Language> {
+    let lines: Vec<&str> = content.lines().collect();
+    let head = lines.iter().take(MODELINE_LINES);
+    let tail = lines.iter().skip(MODELINE_LINES).rev().take(MODELINE_LINES);
+    head.chain(tail).find_map(|line| {
+        if let Some(caps) = VIM_MODELINE.captures(line) {
+            return find(&caps[1]);
+        }
+        let caps = EMACS_MODELINE.captures(line)?;
+        let modeline = caps[1].trim();
+        if !modeline.contains(':') {
+            return find(modeline);
+        }
+        modeline.split(';').find_map(|var| {
+            let (key, value) = var.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case("mode") {
+                find(value)
+            } else {
+                None
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::*;
+
+    fn detect_name(path: &str, content: &str) -> Option<&'static str> {
+        detect(Path::new(path), content).map(|l| l.name)
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(find("go").map(|l| l.name), Some("Go"));
+        assert_eq!(find("Go").map(|l| l.name), Some("Go"));
+        assert_eq!(find("golang").map(|l| l.name), Some("Go"));
+        assert_eq!(find("C++").map(|l| l.name), Some("C++"));
+        assert_eq!(find("cobol"), None);
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(detect_name("src/main.rs", ""), Some("Rust"));
+        assert_eq!(detect_name("cmd/Main.GO", ""), Some("Go"));
+        assert_eq!(detect_name("Makefile", "all:\n"), Some("Makefile"));
+        assert_eq!(
+            detect_name("build/Dockerfile", "FROM scratch\n"),
+            Some("Dockerfile")
+        );
+        assert_eq!(
+            detect_name("bin/tool", "#!/usr/bin/env python3\n"),
+            Some("Python")
+        );
+        assert_eq!(detect_name("bin/run", "#!/bin/bash -e\n"), Some("Shell"));
+        assert_eq!(detect_name("README", "plain text"), None);
+    }
+
+    #[test]
+    fn modelines() {
+        assert_eq!(detect_name("script", "# vim: set ft=ruby:\n"), Some("Ruby"));
+        assert_eq!(detect_name("x.txt", ";; -*- mode: lua; -*-\n"), Some("Lua"));
+        assert_eq!(detect_name("x.txt", "/* -*- C++ -*- */\n"), Some("C++"));
+        assert_eq!(
+            detect_name("conf.h", "int a;\n\n\n\n\n\n\n// vim: syntax=cpp\n"),
+            Some("C++")
+        );
+    }
+}
@@ -1,347 +1,1450 @@
-use ignore::WalkBuilder;
+use builder::IndexBuilder;
+use docid::DocIds;
+use history::History;
 use ngram::split_ngrams;
+use pipeline::Update;
+use positions::Positions;
+use postings::FileIds;
 use puffin_query::QueryNode;
+use reader::{Grams, IndexReader};
+use shard::ShardBuilder;
 use sstable::SSTable;
-use std::collections::hash_map::DefaultHasher;
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
-use std::fs::OpenOptions;
-use std::hash::{Hash, Hasher};
-use std::io::{self, BufWriter};
-use std::iter::Peekable;
-use std::vec;
-use std::{fs, io::Read};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+mod builder;
+mod docid;
+mod encoding;
+mod git;
+mod history;
+mod lang;
+mod matchtree;
+mod metadata;
 mod ngram;
+mod options;
+mod pipeline;
+mod positions;
+mod postings;
+mod reader;
+mod regexp;
+mod result;
+mod score;
+mod shard;
+mod sharded;
+mod stats;
+mod symbols;
+#[cfg(test)]
+mod testutil;
 
-const MAX_SIZE: usize = 2 << 20;
+pub use metadata::Metadata;
+pub use options::{IndexOptions, SearchOptions};
+pub use result::{FileMatch, LineMatch, Match, SearchResult, SymbolMatch};
+pub use search::file::Kind as DocumentKind;
+pub use search::symbol::Kind as SymbolKind;
+pub use search::{Branch, Commit};
+pub use shard::ShardReader;
+pub use sharded::ShardedIndex;
+pub use stats::Stats;
+
+/// Memory the n-grams may take while indexing, before they spill to disk.
+const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
 
 pub mod search {
     include!(concat!(env!("OUT_DIR"), "/search.rs"));
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-pub struct FileId(u64);
+pub struct FileId(u32);
 
-#[derive(Clone, Default)]
-struct FileIds(Vec<FileId>);
+impl TryFrom<Vec<u8>> for FileId {
+    type Error = io::Error;
 
-impl FileIds {
-    fn insert(&mut self, other: FileId) {
-        self.0.push(other);
+    fn try_from(value: Vec<u8>) -> Result<Self, io::Error> {
+        let bytes = value.try_into().map_err(|value: Vec<u8>| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("file id of {} bytes", value.len()),
+            )
+        })?;
+        Ok(FileId(u32::from_le_bytes(bytes)))
     }
 }
 
-impl From<Vec<u8>> for FileIds {
-    fn from(value: Vec<u8>) -> Self {
-        Self(
-            value
-                .chunks(8)
-                .map(|chunk| {
-                    let mut bytes = [0; 8];
-                    bytes.copy_from_slice(chunk);
-                    FileId(u64::from_le_bytes(bytes))
-                })
-                .collect::<Vec<_>>(),
-        )
+impl From<FileId> for Vec<u8> {
+    fn from(val: FileId) -> Self {
+        u32::to_le_bytes(val.0).to_vec()
     }
 }
 
-impl From<FileIds> for Vec<u8> {
-    fn from(val: FileIds) -> Self {
-        val.0.iter().flat_map(|v| u64::to_le_bytes(v.0)).collect()
-    }
+pub struct Index {
+    loc: String,
+    doc_ids: DocIds,
+    content_ngrams: SSTable<FileIds<'static>>,
+    path_ngrams: SSTable<FileIds<'static>>,
+    /// N-grams of the names of definitions found in the files.
+    symbol_ngrams: SSTable<FileIds<'static>>,
+    /// Offsets of content trigrams in every file, if enabled.
+    content_positions: Option<SSTable<Positions>>,
+    file_meta: Metadata<FileId, search::File>,
+    /// Files found in the indexed directories but left out of the index.
+    skipped: Metadata<FileId, search::SkippedFile>,
+    languages: BTreeMap<&'static str, BTreeSet<FileId>>,
+    /// Files of every repository, by repository name.
+    repositories: BTreeMap<String, BTreeSet<FileId>>,
+    /// Files indexed from git objects, by branch name.
+    branches: BTreeMap<String, BTreeSet<FileId>>,
+    /// Documents of the commit history.
+    history: History,
+    memory_budget: usize,
 }
 
-pub struct Metadata<K, V> {
-    data: BTreeMap<K, V>,
-}
+impl Index {
+    pub fn new(loc: &str) -> Self {
+        // Creating the table makes sure the index directory exists.
+        let content_ngrams = open_table(loc);
+        Index {
+            loc: loc.to_string(),
+            doc_ids: DocIds::load(loc)
+                .unwrap_or_else(|err| panic!("failed to load doc ids from {}: {}", loc, err)),
+            content_ngrams,
+            path_ngrams: open_table(&format!("{}/paths", loc)),
+            symbol_ngrams: open_table(&format!("{}/symbols", loc)),
+            content_positions: None,
+            file_meta: Metadata::new(Path::new(loc).join("metadata")),
+            skipped: Metadata::new(Path::new(loc).join("skipped")),
+            languages: BTreeMap::new(),
+            repositories: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            history: History::default(),
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+        }
+    }
 
-impl<K, V> Metadata<K, V>
-where
-    V: prost::Message,
-{
-    pub fn flush(&mut self) -> Result<(), io::Error> {
-        let name = "metadata";
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(format!("database/{}", name))
-            .unwrap();
+    /// Creates an index that also records positions of the content trigrams.
+    /// This makes the index larger, but allows exact queries to be verified
+    /// without scanning the file content.
+    pub fn with_positions(loc: &str) -> Self {
+        Index {
+            content_positions: Some(open_table(&format!("{}/positions", loc))),
+            ..Index::new(loc)
+        }
+    }
 
-        let mut data_writer = BufWriter::new(file);
+    /// Opens an index previously written to `loc` by `flush`, so it can be
+    /// searched without indexing the source tree again.
+    pub fn open(loc: &str) -> Result<Self, io::Error> {
+        let file_meta = Metadata::open(Path::new(loc).join("metadata"))?;
+        let skipped = match Metadata::open(Path::new(loc).join("skipped")) {
+            Ok(skipped) => skipped,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Metadata::new(Path::new(loc).join("skipped"))
+            }
+            Err(err) => return Err(err),
+        };
+        let positions = format!("{}/positions", loc);
+        let mut index = Index {
+            doc_ids: DocIds::load(loc)?,
+            content_positions: Path::new(&positions)
+                .is_dir()
+                .then(|| SSTable::new(&positions, 100000))
+                .transpose()?,
+            file_meta,
+            skipped,
+            ..Index::new(loc)
+        };
 
-        for (k, v) in self.data.iter() {
-            // data_writer.write(k);
+        // Languages, repositories, branches and the history are cheap to
+        // recover from the files.
+        for (file_id, file) in index.file_meta.iter() {
+            if let Some(language) = lang::find(&file.file_type) {
+                index
+                    .languages
+                    .entry(language.name)
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+            if let Some(repository) = file.repository.as_ref() {
+                index
+                    .repositories
+                    .entry(repository.name.clone())
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+            for branch in file.branches.iter() {
+                index
+                    .branches
+                    .entry(branch.name.clone())
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+            index.history.add(file_id, file);
         }
+        Ok(index)
+    }
+
+    /// Sets how many bytes the collected n-grams may take while indexing. The
+    /// budget is split between the tokenizing threads and the builder merging
+    /// their n-grams, which spills them into sorted runs in the index
+    /// directory once its half is full.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = bytes;
+    }
 
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.doc_ids.flush(&self.loc)?;
+        self.file_meta.flush()?;
+        self.skipped.flush()?;
+        self.content_ngrams.flush()?;
+        self.path_ngrams.flush()?;
+        self.symbol_ngrams.flush()?;
+        if let Some(positions) = self.content_positions.as_mut() {
+            positions.flush()?;
+        }
         Ok(())
     }
-}
 
-pub struct Index {
-    content_ngrams: SSTable<FileIds>,
-    file_meta: BTreeMap<FileId, Vec<search::File>>,
-}
+    /// Writes the whole index into a single shard file that can be searched
+    /// through `ShardReader`.
+    pub fn write_shard(&self, path: impl AsRef<Path>) -> Result<(), io::Error> {
+        let mut builder = ShardBuilder::new(self.content_positions.is_some());
+        for (file_id, file) in self.file_meta.iter() {
+            builder.add(file_id, file);
+        }
+        builder.write(path.as_ref())
+    }
 
-impl Index {
-    pub fn new(loc: &str) -> Self {
-        Index {
-            content_ngrams: SSTable::new(loc, 100000),
-            file_meta: BTreeMap::new(),
+    /// Returns the files that were found while indexing but left out, along
+    /// with the reason they were skipped.
+    pub fn skipped_files(&self) -> impl Iterator<Item = &search::SkippedFile> {
+        self.skipped.iter().map(|(_, file)| file)
+    }
+
+    /// Counts the files of the index, by language, repository and branch.
+    pub fn stats(&self) -> Stats {
+        // Documents of the history have languages and repositories as well,
+        // but they aren't files.
+        let count = |name: &str, file_ids: &BTreeSet<FileId>| {
+            let files = file_ids
+                .iter()
+                .filter(|file_id| !self.history.contains(file_id))
+                .count();
+            (files > 0).then(|| (name.to_string(), files))
+        };
+        let mut stats = Stats {
+            skipped_files: self.skipped.iter().count(),
+            languages: self
+                .languages
+                .iter()
+                .filter_map(|(name, file_ids)| count(name, file_ids))
+                .collect(),
+            repositories: self
+                .repositories
+                .iter()
+                .filter_map(|(name, file_ids)| count(name, file_ids))
+                .collect(),
+            branches: self
+                .branches
+                .iter()
+                .filter_map(|(name, file_ids)| count(name, file_ids))
+                .collect(),
+            commits: self.history.commits().len(),
+            diffs: self.history.diffs().len(),
+            ..Default::default()
+        };
+        for (file_id, file) in self.file_meta.iter() {
+            if !self.history.contains(file_id) {
+                stats.files += 1;
+                stats.content_bytes += file.content.len() as u64;
+            }
         }
+        stats
     }
 
-    pub fn flush(&mut self) -> Result<(), io::Error> {
-        self.content_ngrams.flush()?;
-        Ok(())
+    /// Returns all files matching the query, best first.
+    pub fn search(&self, query: QueryNode) -> Vec<FileMatch> {
+        self.search_with_options(query, &SearchOptions::default())
+            .files
+    }
+
+    /// Returns the files matching the query within the limits of the options.
+    pub fn search_with_options(&self, query: QueryNode, options: &SearchOptions) -> SearchResult {
+        reader::search(self, query, options)
+    }
+
+    /// Returns paths of all indexed files that contain the given fragment.
+    pub fn find_files(&self, fragment: &str) -> Vec<String> {
+        reader::find_files(self, fragment)
+    }
+
+    /// Indexes all files in the directory. Files indexed before are only
+    /// indexed again if they changed, and files that disappeared from the
+    /// directory are removed from the index.
+    pub fn index(&mut self, dir_path: &str) -> Result<(), io::Error> {
+        self.index_with_options(dir_path, &IndexOptions::default())
     }
 
-    pub fn search(&self, query: QueryNode) -> Vec<search::File> {
-        let mut results: Vec<FileId> = Vec::new();
-        let keys = self.file_meta.keys().cloned().collect();
-        let mut iters = Box::new(And::new(vec![
-            Box::new(All::new(keys)),
-            self.match_iter(query),
-        ]));
-        while let Some(fid) = iters.next() {
-            results.push(fid);
+    /// Same as `index`, with the files to index selected by the options.
+    /// The index comes out the same no matter how many threads ran.
+    pub fn index_with_options(
+        &mut self,
+        dir_path: &str,
+        options: &IndexOptions,
+    ) -> Result<(), io::Error> {
+        // New files get their ids in the order of their paths, not in the
+        // order the walker happened to find them.
+        let (paths, invalid) = pipeline::walk(dir_path, options)?;
+        let mut files: Vec<(FileId, PathBuf)> = paths
+            .into_iter()
+            .filter_map(|path| Some((self.doc_ids.get_or_insert(path.to_str()?), path)))
+            .collect();
+        files.sort();
+
+        let builder = Mutex::new(IndexBuilder::new(
+            Path::new(&self.loc).join("runs"),
+            self.memory_budget,
+        ));
+        let batch = pipeline::tokenize(
+            &files,
+            self.content_positions.is_some(),
+            options,
+            &builder,
+            |file_id, path, contents| {
+                let known = self.file_meta.get(file_id);
+                pipeline::process(path, known, options.max_file_size, contents)
+            },
+        )?;
+
+        // Skipped files are recorded again if they are still around.
+        let skipped: Vec<FileId> = self
+            .skipped
+            .iter()
+            .filter(|(_, file)| Path::new(&file.filename).starts_with(dir_path))
+            .map(|(file_id, _)| file_id.clone())
+            .collect();
+        for file_id in skipped {
+            self.skipped.remove(&file_id);
+        }
+        for path in invalid {
+            let filename = path.to_string_lossy();
+            log::warn!("skipping {}: the path isn't valid UTF-8", filename);
+            self.skipped.insert(
+                self.doc_ids.get_or_insert(&filename),
+                search::SkippedFile {
+                    filename: filename.to_string(),
+                    reason: search::skipped_file::Reason::InvalidPath.into(),
+                },
+            );
         }
 
-        results
+        let repository = options.repository_at(dir_path);
+        let mut seen = BTreeSet::new();
+        for (file_id, update) in batch.updates {
+            match update {
+                Update::Unchanged => {}
+                Update::Touched { mtime, size } => {
+                    if let Some(file) = self.file_meta.get_mut(&file_id) {
+                        file.mtime = mtime;
+                        file.size = size;
+                    }
+                }
+                Update::Indexed(file, language) => self.add_file(&file_id, *file, language)?,
+                Update::Skipped(reason) => {
+                    let filename = self.doc_ids.path(&file_id).unwrap_or_default();
+                    self.skipped.insert(
+                        file_id,
+                        search::SkippedFile {
+                            filename: filename.to_string(),
+                            reason: reason.into(),
+                        },
+                    );
+                    continue;
+                }
+            }
+            self.set_repository(&file_id, &repository);
+            seen.insert(file_id);
+        }
+
+        // Files indexed from git objects are left to `index_branch`, the
+        // history to `index_history`.
+        let removed: Vec<FileId> = self
+            .file_meta
             .iter()
-            .flat_map(|f| self.file_meta.get(f).cloned().unwrap_or_default())
-            .collect()
+            .filter(|(file_id, file)| {
+                !seen.contains(file_id)
+                    && file.branches.is_empty()
+                    && file.kind() == DocumentKind::File
+                    && Path::new(&file.filename).starts_with(dir_path)
+            })
+            .map(|(file_id, _)| file_id.clone())
+            .collect();
+        for file_id in removed {
+            log::info!("removing {:?}", file_id);
+            self.remove_file(&file_id)?;
+        }
+
+        self.merge_postings(builder.into_inner().unwrap(), batch.positions)
     }
 
-    pub fn index(&mut self, dir_path: &str) {
-        let walker = WalkBuilder::new(dir_path).standard_filters(true).build();
-        let mut contents = String::new();
+    /// Indexes a branch of the git repository in `repo_path` straight from
+    /// its objects, without checking the branch out. Any revision naming a
+    /// commit works as a branch. Several branches can be indexed, files that
+    /// are identical in more of them are stored once. Indexing a branch
+    /// again replaces its files.
+    pub fn index_branch(
+        &mut self,
+        repo_path: &str,
+        branch: &str,
+        options: &IndexOptions,
+    ) -> Result<(), io::Error> {
+        let repo = Path::new(repo_path);
+        let commit = git::resolve(repo, branch)?;
+        let mut blobs = git::BlobReader::new(repo)?;
+        let entries = pipeline::select(repo, git::list_files(repo, &commit)?, &mut blobs, options)?;
+
+        // Documents are keyed by their path and content, so that identical
+        // files of different branches share their id.
+        let mut files: Vec<(FileId, (PathBuf, git::Entry))> = entries
+            .into_iter()
+            .map(|entry| {
+                let path = repo.join(&entry.path);
+                let key = format!("{}@{}", path.to_str().unwrap(), entry.blob);
+                (self.doc_ids.get_or_insert(&key), (path, entry))
+            })
+            .collect();
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let in_branch: BTreeSet<FileId> = files.iter().map(|(id, _)| id.clone()).collect();
+        files.retain(|(file_id, _)| self.file_meta.get(file_id).is_none());
 
-        for entry in walker {
-            let entry = entry.expect("Unable to get directory entry");
-            let path = entry.path();
+        let blobs = Mutex::new(blobs);
+        let builder = Mutex::new(IndexBuilder::new(
+            Path::new(&self.loc).join("runs"),
+            self.memory_budget,
+        ));
+        let batch = pipeline::tokenize(
+            &files,
+            self.content_positions.is_some(),
+            options,
+            &builder,
+            |_, (path, entry), contents| {
+                pipeline::process_blob(path, entry, &blobs, options.max_file_size, contents)
+            },
+        )?;
 
-            log::info!("indexing {:?}", path);
+        for (file_id, update) in batch.updates {
+            match update {
+                Update::Indexed(file, language) => self.add_file(&file_id, *file, language)?,
+                Update::Skipped(reason) => {
+                    let index = files.binary_search_by(|(id, _)| id.cmp(&file_id)).unwrap();
+                    let (path, _) = &files[index].1;
+                    self.skipped.insert(
+                        file_id,
+                        search::SkippedFile {
+                            filename: path.to_str().unwrap().to_string(),
+                            reason: reason.into(),
+                        },
+                    );
+                }
+                Update::Unchanged | Update::Touched { .. } => {}
+            }
+        }
 
-            if path.is_file() {
-                let mut file = fs::File::open(path).expect("Unable to open file");
+        let repository = options.repository_at(repo_path);
+        let branch = search::Branch {
+            name: branch.to_string(),
+            commit,
+        };
+        for file_id in in_branch.iter() {
+            self.set_repository(file_id, &repository);
+            self.set_branch(file_id, &branch);
+        }
+        let left: Vec<FileId> = self
+            .file_meta
+            .iter()
+            .filter(|(file_id, file)| {
+                !in_branch.contains(file_id)
+                    && file.branches.iter().any(|b| b.name == branch.name)
+                    && Path::new(&file.filename).starts_with(repo_path)
+            })
+            .map(|(file_id, _)| file_id.clone())
+            .collect();
+        for file_id in left {
+            self.unset_branch(&file_id, &branch.name)?;
+        }
 
-                contents.clear();
-                match file.read_to_string(&mut contents) {
-                    Ok(size) => {
-                        if size > MAX_SIZE {
-                            println!("skipping {:?}, too large", path);
-                            continue;
-                        }
-                    }
-                    Err(_) => continue,
-                };
+        self.merge_postings(builder.into_inner().unwrap(), batch.positions)
+    }
+
+    /// Indexes the history of the git repository in `repo_path` leading to
+    /// the revision: the message of every commit, and the lines it added or
+    /// removed in every file. These documents are only matched by queries
+    /// with a `type:`, `author:`, `before:` or `after:` atom. Commits indexed
+    /// before are kept, so indexing the revision again as it moves on only
+    /// adds the new commits. Diffs larger than the maximum file size are
+    /// left out.
+    pub fn index_history(
+        &mut self,
+        repo_path: &str,
+        revision: &str,
+        options: &IndexOptions,
+    ) -> Result<(), io::Error> {
+        let repo = Path::new(repo_path);
+        let commit = git::resolve(repo, revision)?;
+        // Every indexed commit has a document holding its message.
+        let ids: Vec<String> = git::rev_list(repo, &commit)?
+            .into_iter()
+            .filter(|id| {
+                let key = format!("{}@{}", repo_path, id);
+                !self
+                    .doc_ids
+                    .get(&key)
+                    .is_some_and(|file_id| self.file_meta.get(&file_id).is_some())
+            })
+            .collect();
 
-                let file_name = path.to_str().unwrap().to_string();
-                let file_id = hash_filename(path.to_str().unwrap());
+        let mut files: Vec<(FileId, search::File)> = Vec::new();
+        for commit in git::log(repo, &ids)? {
+            for (key, file) in history::documents(repo, &commit) {
+                if file.size > options.max_file_size {
+                    log::info!(
+                        "skipping diff of {:?} in {}, too large",
+                        file.filename,
+                        commit.id
+                    );
+                    continue;
+                }
+                files.push((self.doc_ids.get_or_insert(&key), file));
+            }
+        }
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-                let files_vec = self.file_meta.entry(file_id.clone()).or_default();
-                files_vec.push(search::File {
-                    filename: file_name,
-                    content: contents.clone(),
-                    file_type: "unknown".into(),
-                });
+        let builder = Mutex::new(IndexBuilder::new(
+            Path::new(&self.loc).join("runs"),
+            self.memory_budget,
+        ));
+        let batch = pipeline::tokenize(
+            &files,
+            self.content_positions.is_some(),
+            options,
+            &builder,
+            |_, file, _| {
+                let language = lang::find(&file.file_type).map(|l| l.name);
+                Update::Indexed(Box::new(file.clone()), language)
+            },
+        )?;
 
-                self.collect_trigrams(&file_id, &contents);
+        let repository = options.repository_at(repo_path);
+        for (file_id, update) in batch.updates {
+            if let Update::Indexed(file, language) = update {
+                self.add_file(&file_id, *file, language)?;
+                self.set_repository(&file_id, &repository);
             }
         }
+
+        self.merge_postings(builder.into_inner().unwrap(), batch.positions)
     }
 
-    pub fn collect_trigrams(&mut self, file_id: &FileId, src: &str) {
-        log::info!("collecting trigrams");
-        for line in src.lines() {
-            for (trigram, _) in split_ngrams(line) {
-                let mut current = self
-                    .content_ngrams
-                    .get(trigram.to_string().as_str())
-                    .unwrap_or_default();
-                current.insert(file_id.clone());
-                self.content_ngrams
-                    .insert(trigram.to_string().as_str(), current)
-                    .unwrap();
+    /// Replaces the file, along with its language.
+    fn add_file(
+        &mut self,
+        file_id: &FileId,
+        file: search::File,
+        language: Option<&'static str>,
+    ) -> Result<(), io::Error> {
+        self.remove_file(file_id)?;
+        if let Some(language) = language {
+            self.languages
+                .entry(language)
+                .or_default()
+                .insert(file_id.clone());
+        }
+        self.history.add(file_id, &file);
+        self.file_meta.insert(file_id.clone(), file);
+        Ok(())
+    }
+
+    /// Adds the n-grams collected by the builder and the positions to the
+    /// tables.
+    fn merge_postings(
+        &mut self,
+        builder: IndexBuilder,
+        positions: Option<BTreeMap<String, Positions>>,
+    ) -> Result<(), io::Error> {
+        // Every posting list is read and written once per run, in the order
+        // of the n-grams.
+        builder.finish(|grams, ngram, ids| {
+            let table = match grams {
+                Grams::Content => &mut self.content_ngrams,
+                Grams::Path => &mut self.path_ngrams,
+                Grams::Symbol => &mut self.symbol_ngrams,
+            };
+            let mut current = table.get(ngram).unwrap_or_default();
+            current.merge(ids);
+            table.insert(ngram, current)
+        })?;
+        if let (Some(table), Some(positions)) = (self.content_positions.as_mut(), positions) {
+            for (trigram, positions) in positions {
+                let mut current = table.get(&trigram).unwrap_or_default();
+                current.merge(positions);
+                table.insert(&trigram, current)?;
             }
         }
-        log::info!("collecting trigrams done");
+        Ok(())
     }
 
-    fn match_iter(&self, query: QueryNode) -> Box<dyn MatchIter> {
-        match query {
-            QueryNode::Or { lhs, rhs } => {
-                Box::new(Or::new(vec![self.match_iter(*lhs), self.match_iter(*rhs)]))
+    /// Moves the file into the repository, unless it is already part of it.
+    fn set_repository(&mut self, file_id: &FileId, repository: &search::Repository) {
+        let Some(file) = self.file_meta.get_mut(file_id) else {
+            return;
+        };
+        if file.repository.as_ref() != Some(repository) {
+            if let Some(previous) = file.repository.replace(repository.clone()) {
+                remove_named(&mut self.repositories, &previous.name, file_id);
             }
-            QueryNode::And { lhs, rhs } => {
-                Box::new(And::new(vec![self.match_iter(*lhs), self.match_iter(*rhs)]))
+        }
+        self.repositories
+            .entry(repository.name.clone())
+            .or_default()
+            .insert(file_id.clone());
+    }
+
+    /// Adds the branch to the file, replacing the commit it was indexed at.
+    fn set_branch(&mut self, file_id: &FileId, branch: &search::Branch) {
+        let Some(file) = self.file_meta.get_mut(file_id) else {
+            return;
+        };
+        match file.branches.iter_mut().find(|b| b.name == branch.name) {
+            Some(known) => known.commit = branch.commit.clone(),
+            None => {
+                file.branches.push(branch.clone());
+                file.branches.sort_by(|a, b| a.name.cmp(&b.name));
             }
-            QueryNode::Not(q) => Box::new(Not::new(self.match_iter(*q))),
-            QueryNode::Lang(_) => todo!(),
-            QueryNode::File(_) => todo!(),
-            QueryNode::Term(t) => Box::new(ContentGrams::new(t, self)),
-            QueryNode::Regex(_) => todo!(),
         }
+        self.branches
+            .entry(branch.name.clone())
+            .or_default()
+            .insert(file_id.clone());
     }
-}
 
-fn hash_filename(filename: &str) -> FileId {
-    let mut s = DefaultHasher::new();
-    filename.hash(&mut s);
-    FileId(s.finish())
-}
+    /// Removes the branch from the file, along with the file once it is no
+    /// longer part of any branch.
+    fn unset_branch(&mut self, file_id: &FileId, name: &str) -> Result<(), io::Error> {
+        let Some(file) = self.file_meta.get_mut(file_id) else {
+            return Ok(());
+        };
+        file.branches.retain(|b| b.name != name);
+        let orphaned = file.branches.is_empty();
+        remove_named(&mut self.branches, name, file_id);
+        if orphaned {
+            log::info!("removing {:?}", file_id);
+            self.remove_file(file_id)?;
+        }
+        Ok(())
+    }
 
-trait MatchIter {
-    fn matches(&self, fid: &FileId) -> bool;
-    fn next(&mut self) -> Option<FileId>;
-}
+    /// Removes the file along with all of its postings.
+    fn remove_file(&mut self, file_id: &FileId) -> Result<(), io::Error> {
+        let Some(file) = self.file_meta.remove(file_id) else {
+            return Ok(());
+        };
 
-struct All {
-    iter: Peekable<vec::IntoIter<FileId>>,
+        let trigrams: BTreeSet<String> = file
+            .content
+            .lines()
+            .flat_map(split_ngrams)
+            .map(|(trigram, _)| trigram.to_string())
+            .collect();
+        for trigram in trigrams.iter() {
+            remove_posting(&mut self.content_ngrams, file_id, trigram)?;
+        }
+        for (trigram, _) in split_ngrams(&file.filename) {
+            remove_posting(&mut self.path_ngrams, file_id, &trigram.to_string())?;
+        }
+        let trigrams: BTreeSet<String> = file
+            .symbols
+            .iter()
+            .flat_map(|symbol| split_ngrams(&symbol.name))
+            .map(|(trigram, _)| trigram.to_string())
+            .collect();
+        for trigram in trigrams.iter() {
+            remove_posting(&mut self.symbol_ngrams, file_id, trigram)?;
+        }
+        if let Some(table) = self.content_positions.as_mut() {
+            for trigram in positions::collect_positions(&file.content).keys() {
+                let mut current = table.get(trigram).unwrap_or_default();
+                current.remove(file_id);
+                table.insert(trigram, current)?;
+            }
+        }
+        for file_ids in self.languages.values_mut() {
+            file_ids.remove(file_id);
+        }
+        if let Some(repository) = file.repository.as_ref() {
+            remove_named(&mut self.repositories, &repository.name, file_id);
+        }
+        for branch in file.branches.iter() {
+            remove_named(&mut self.branches, &branch.name, file_id);
+        }
+        self.history.remove(file_id, &file);
+        Ok(())
+    }
 }
 
-impl All {
-    pub fn new(iter: Vec<FileId>) -> Self {
-        Self {
-            iter: iter.into_iter().peekable(),
+/// Removes the file from the named set of files, such as a repository, along
+/// with the set once no file is left.
+fn remove_named(sets: &mut BTreeMap<String, BTreeSet<FileId>>, name: &str, file_id: &FileId) {
+    if let Some(file_ids) = sets.get_mut(name) {
+        file_ids.remove(file_id);
+        if file_ids.is_empty() {
+            sets.remove(name);
         }
     }
 }
 
-impl MatchIter for All {
-    fn matches(&self, _: &FileId) -> bool {
-        true
+impl IndexReader for Index {
+    fn postings(&self, grams: Grams, ngram: &str) -> FileIds<'_> {
+        let table = match grams {
+            Grams::Content => &self.content_ngrams,
+            Grams::Path => &self.path_ngrams,
+            Grams::Symbol => &self.symbol_ngrams,
+        };
+        table.get(ngram).unwrap_or_default()
     }
 
-    fn next(&mut self) -> Option<FileId> {
-        self.iter.next()
+    fn has_positions(&self) -> bool {
+        self.content_positions.is_some()
     }
-}
 
-struct Not(Box<dyn MatchIter>);
+    fn positions(&self, trigram: &str) -> Positions {
+        self.content_positions
+            .as_ref()
+            .and_then(|table| table.get(trigram))
+            .unwrap_or_default()
+    }
 
-impl Not {
-    fn new(q: Box<dyn MatchIter>) -> Self {
-        Not(q)
+    fn file(&self, file_id: &FileId) -> Option<Cow<'_, search::File>> {
+        self.file_meta.get(file_id).map(Cow::Borrowed)
+    }
+
+    fn filename(&self, file_id: &FileId) -> Option<&str> {
+        self.file_meta.get(file_id).map(|f| f.filename.as_str())
+    }
+
+    fn file_ids(&self) -> Box<dyn Iterator<Item = FileId> + '_> {
+        Box::new(self.file_meta.keys().cloned())
+    }
+
+    fn language(&self, name: &str) -> BTreeSet<FileId> {
+        self.languages.get(name).cloned().unwrap_or_default()
+    }
+
+    fn repositories(&self) -> &BTreeMap<String, BTreeSet<FileId>> {
+        &self.repositories
     }
-}
 
-impl MatchIter for Not {
-    fn matches(&self, fid: &FileId) -> bool {
-        !self.0.matches(fid)
+    fn branches(&self) -> &BTreeMap<String, BTreeSet<FileId>> {
+        &self.branches
     }
 
-    fn next(&mut self) -> Option<FileId> {
-        None
+    fn history(&self) -> &History {
+        &self.history
     }
 }
 
-struct Or {
-    iterators: Vec<Box<dyn MatchIter>>,
+/// Opens the table in the directory, panicking if it can't be read.
+fn open_table<V>(dir_name: &str) -> SSTable<V>
+where
+    V: Clone + From<Vec<u8>> + Into<Vec<u8>>,
+{
+    SSTable::new(dir_name, 100000)
+        .unwrap_or_else(|err| panic!("failed to open table {}: {}", dir_name, err))
 }
 
-impl Or {
-    pub fn new(iterators: Vec<Box<dyn MatchIter>>) -> Self {
-        Self { iterators }
+/// Removes the file from the posting list of the n-gram, along with the
+/// n-gram once no file is left.
+fn remove_posting(
+    table: &mut SSTable<FileIds<'static>>,
+    file_id: &FileId,
+    ngram: &str,
+) -> Result<(), io::Error> {
+    let Some(mut current) = table.get(ngram) else {
+        return Ok(());
+    };
+    current.remove(file_id);
+    if current.is_empty() {
+        table.delete(ngram)
+    } else {
+        table.insert(ngram, current)
     }
 }
 
-impl MatchIter for Or {
-    fn matches(&self, fid: &FileId) -> bool {
-        self.iterators.iter().any(|i| i.matches(fid))
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{git, has_git, Fixture};
+    use crate::*;
+    use std::fs;
+
+    fn filenames(index: &Index, query: &str) -> Vec<String> {
+        let mut result: Vec<String> = index
+            .search(QueryNode::new(query))
+            .into_iter()
+            .map(|m| m.filename)
+            .collect();
+        result.sort();
+        result
     }
 
-    fn next(&mut self) -> Option<FileId> {
-        'outer: loop {
-            let current = self.iterators.first_mut().and_then(|i| i.next());
-            current.as_ref()?;
-            let current = current.unwrap();
-            for iterator in self.iterators.iter_mut().skip(1) {
-                if !iterator.matches(&current) {
-                    continue 'outer;
-                }
-            }
-            return Some(current);
+    #[test]
+    fn test_incremental_index() {
+        let fixture = Fixture::new();
+        let src = fixture.write_files(
+            "src",
+            &[
+                ("a.rs", "fn alpha() {}\n"),
+                ("b.rs", "fn beta() {}\n"),
+                ("c.rs", "fn gamma() {}\n"),
+            ],
+        );
+        let loc = fixture.path("index");
+
+        let mut index = Index::new(&loc);
+        index.index(&src).unwrap();
+        index.flush().unwrap();
+        assert_eq!(filenames(&index, "fn").len(), 3);
+
+        // Changed, deleted and added files, re-indexed from the flushed index.
+        fs::write(format!("{}/a.rs", src), "fn delta() {}\n").unwrap();
+        fs::remove_file(format!("{}/b.rs", src)).unwrap();
+        fs::write(format!("{}/d.rs", src), "fn epsilon() {}\n").unwrap();
+
+        let mut index = Index::open(&loc).unwrap();
+        index.index(&src).unwrap();
+        index.flush().unwrap();
+
+        let index = Index::open(&loc).unwrap();
+        assert_eq!(
+            filenames(&index, "fn"),
+            vec![
+                format!("{}/a.rs", src),
+                format!("{}/c.rs", src),
+                format!("{}/d.rs", src)
+            ]
+        );
+        assert!(filenames(&index, "alpha").is_empty());
+        assert!(filenames(&index, "beta").is_empty());
+        assert_eq!(filenames(&index, "delta"), vec![format!("{}/a.rs", src)]);
+        assert_eq!(filenames(&index, "lang:rust").len(), 3);
+        assert!(index.find_files("b.rs").is_empty());
+    }
+
+    #[test]
+    fn test_encodings() {
+        let fixture = Fixture::new();
+        let src = fixture.path("src");
+        let loc = fixture.path("index");
+        fixture.write("src/latin1.c", b"/* caf\xe9 */\n");
+        fixture.write("src/utf16.txt", b"\xff\xfec\x00a\x00f\x00\xe9\x00");
+        fixture.write("src/binary.o", b"\x7fELF\x02\x01\x00caf\xe9");
+
+        let mut index = Index::new(&loc);
+        index.index(&src).unwrap();
+        index.flush().unwrap();
+
+        let index = Index::open(&loc).unwrap();
+        assert_eq!(
+            filenames(&index, "\"caf\u{e9}\""),
+            vec![format!("{}/latin1.c", src), format!("{}/utf16.txt", src)]
+        );
+        assert_eq!(
+            index
+                .skipped_files()
+                .map(|file| (file.filename.as_str(), file.reason()))
+                .collect::<Vec<_>>(),
+            vec![(
+                format!("{}/binary.o", src).as_str(),
+                search::skipped_file::Reason::Binary
+            )]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let fixture = Fixture::new();
+        let src = fixture.write_files("src", &[("main.go", "func main() {}\n")]);
+        let loc = fixture.path("index");
+        let invalid = Path::new(&src).join(OsStr::from_bytes(b"caf\xe9.go"));
+        fs::write(&invalid, "func invalid() {}\n").unwrap();
+
+        let mut index = Index::new(&loc);
+        index.index(&src).unwrap();
+        assert_eq!(filenames(&index, "func"), vec![format!("{}/main.go", src)]);
+        assert_eq!(
+            index
+                .skipped_files()
+                .map(|file| (file.filename.clone(), file.reason()))
+                .collect::<Vec<_>>(),
+            vec![(
+                format!("{}/caf\u{fffd}.go", src),
+                search::skipped_file::Reason::InvalidPath
+            )]
+        );
+    }
+
+    #[test]
+    fn test_index_options() {
+        let fixture = Fixture::new();
+        let main = "fn main() {}\n";
+        let large = "// large\n".repeat(100);
+        let src = fixture.write_files(
+            "src",
+            &[
+                ("a.rs", main),
+                ("b.rs", main),
+                ("c.txt", main),
+                (".hidden.rs", main),
+                ("target/d.rs", main),
+                ("vendor/e.rs", main),
+                ("large.rs", &large),
+                (".puffinignore", "vendor/\n"),
+            ],
+        );
+
+        let mut index = Index::new(&fixture.path("index"));
+        let options = IndexOptions::new()
+            .max_file_size(100)
+            .ignore("target/")
+            .include("*.rs")
+            .exclude("b.*");
+        index.index_with_options(&src, &options).unwrap();
+        assert_eq!(filenames(&index, "fn"), vec![format!("{}/a.rs", src)]);
+        assert_eq!(
+            index
+                .skipped_files()
+                .map(|file| (file.filename.as_str(), file.reason()))
+                .collect::<Vec<_>>(),
+            vec![(
+                format!("{}/large.rs", src).as_str(),
+                search::skipped_file::Reason::TooLarge
+            )]
+        );
+
+        let mut index = Index::new(&fixture.path("limited"));
+        let options = IndexOptions::new().hidden(true).max_file_count(2);
+        index.index_with_options(&src, &options).unwrap();
+        assert_eq!(filenames(&index, "fn"), vec![format!("{}/.hidden.rs", src)]);
+    }
+
+    #[test]
+    fn test_case_sensitivity() {
+        let fixture = Fixture::new();
+        let src = fixture.write_files(
+            "src",
+            &[
+                ("lower.go", "type merchant struct{}\n"),
+                ("Upper.go", "type Merchant struct{}\n"),
+                ("CAPS.go", "const MERCHANT = 1\n"),
+            ],
+        );
+
+        for loc in ["index", "positions"] {
+            let loc = fixture.path(loc);
+            let mut index = match loc.ends_with("positions") {
+                true => Index::with_positions(&loc),
+                false => Index::new(&loc),
+            };
+            index.index(&src).unwrap();
+
+            let count = |query: &str| filenames(&index, query).len();
+            assert_eq!(count("merchant"), 3);
+            assert_eq!(count("Merchant"), 1);
+            assert_eq!(count("case:no Merchant"), 3);
+            assert_eq!(count("case:yes merchant"), 1);
+            assert_eq!(count("/merch[a-z]+/"), 3);
+            assert_eq!(count("/Merch[a-z]+/"), 1);
+            assert_eq!(count("file:upper"), 1);
+            assert_eq!(count("file:Upper AND NOT Merchant"), 0);
         }
     }
-}
 
-struct And {
-    current: Option<FileId>,
-    iterators: Vec<Box<dyn MatchIter>>,
-}
+    #[test]
+    fn test_symbols() {
+        let fixture = Fixture::new();
+        let src = fixture.write_files(
+            "src",
+            &[
+                (
+                    "payments.go",
+                    "package payments\n\nfunc ProcessPayment() error {\n\treturn nil\n}\n",
+                ),
+                (
+                    "main.go",
+                    "package main\n\nfunc main() {\n\tpayments.ProcessPayment()\n}\n",
+                ),
+            ],
+        );
+        let loc = fixture.path("index");
+
+        let mut index = Index::new(&loc);
+        index.index(&src).unwrap();
+        assert_eq!(filenames(&index, "ProcessPayment").len(), 2);
+        assert_eq!(
+            filenames(&index, "sym:processpayment"),
+            vec![format!("{}/payments.go", src)]
+        );
+        assert!(filenames(&index, "sym:ProcessPayments").is_empty());
+        assert_eq!(filenames(&index, "sym:/^main$/").len(), 1);
+
+        let matches = index.search(QueryNode::new("sym:Payment"));
+        assert_eq!(
+            matches[0].symbols,
+            vec![SymbolMatch {
+                name: "ProcessPayment".into(),
+                kind: SymbolKind::Function,
+                line_number: 3,
+                byte_range: 23..37,
+            }]
+        );
+        assert_eq!(matches[0].line_matches[0].line_number, 3);
+
+        // Removed definitions are no longer found.
+        fs::write(format!("{}/payments.go", src), "package payments\n").unwrap();
+        index.index(&src).unwrap();
+        assert!(filenames(&index, "sym:ProcessPayment").is_empty());
+    }
+
+    #[test]
+    fn test_search_options() {
+        let fixture = Fixture::new();
+        for i in 0..10 {
+            fixture.write(&format!("src/{}.rs", i), "return err;\n".repeat(i + 1));
+        }
+        let src = fixture.path("src");
+        let loc = fixture.path("index");
+        let mut index = Index::new(&loc);
+        index.index(&src).unwrap();
 
-impl And {
-    pub fn new(iterators: Vec<Box<dyn MatchIter>>) -> Self {
-        let mut x = Self {
-            current: None,
-            iterators,
+        let search =
+            |options: SearchOptions| index.search_with_options(QueryNode::new("err"), &options);
+        let all = search(SearchOptions::new());
+        assert_eq!(all.files.len(), 10);
+        assert!(!all.truncated);
+        assert!(!search(SearchOptions::new().max_files(10)).truncated);
+
+        let top = search(SearchOptions::new().max_files(3));
+        assert!(top.truncated);
+        assert_eq!(top.files, all.files[..3]);
+
+        let lines = search(SearchOptions::new().max_line_matches(2));
+        assert!(lines.truncated);
+        assert!(lines.files.iter().all(|f| f.line_matches.len() <= 2));
+
+        // Files are evaluated in the order of their ids, 0.rs having one
+        // match, 1.rs two and 2.rs three.
+        let first = search(SearchOptions::new().max_matches(4));
+        assert!(first.truncated);
+        assert_eq!(first.files.len(), 3);
+
+        // Limiting the files alone still evaluates all of them, the best one
+        // being the last. Limiting the matches stops after the third.
+        let best = search(SearchOptions::new().max_files(1));
+        assert!(best.files[0].filename.ends_with("/9.rs"));
+        let stopped = search(SearchOptions::new().max_files(1).max_matches(4));
+        assert!(stopped.truncated);
+        assert!(stopped.files[0].filename.ends_with("/2.rs"));
+
+        let late = search(SearchOptions::new().time_budget(std::time::Duration::ZERO));
+        assert!(late.truncated);
+        assert_eq!(late.files.len(), 1);
+    }
+
+    #[test]
+    fn test_repositories() {
+        let fixture = Fixture::new();
+        let charge = "package main\n\nfunc charge() {}\n";
+        let payments = fixture.write_files("payments", &[("src/charge.go", charge)]);
+        let billing = fixture.write_files(
+            "billing",
+            &[
+                ("src/charge.go", charge),
+                ("README.md", "Charges customers.\n"),
+            ],
+        );
+        let loc = fixture.path("index");
+
+        let mut index = Index::new(&loc);
+        index.index(&payments).unwrap();
+        let options = IndexOptions::new()
+            .repository("billing-service")
+            .url_template("https://git.example.com/billing/blob/main/{path}#L{line}");
+        index.index_with_options(&billing, &options).unwrap();
+
+        assert_eq!(filenames(&index, "charge").len(), 3);
+        assert_eq!(
+            filenames(&index, "charge AND repo:payments"),
+            vec![format!("{}/src/charge.go", payments)]
+        );
+        assert_eq!(filenames(&index, "repo:/^billing-/").len(), 2);
+        assert!(filenames(&index, "repo:/^billing$/").is_empty());
+
+        let result = index.search_with_options(QueryNode::new("func"), &SearchOptions::new());
+        let groups: Vec<(&str, usize)> = result
+            .by_repository()
+            .into_iter()
+            .map(|(name, files)| (name, files.len()))
+            .collect();
+        assert_eq!(groups.len(), 2);
+        assert!(groups.contains(&("payments", 1)) && groups.contains(&("billing-service", 1)));
+        let urls: Vec<Option<String>> = result.files.iter().map(|f| f.url.clone()).collect();
+        assert!(urls.contains(&None));
+        assert!(urls.contains(&Some(
+            "https://git.example.com/billing/blob/main/src/charge.go#L3".into()
+        )));
+
+        // Repositories survive reopening the index.
+        index.flush().unwrap();
+        let index = Index::open(&loc).unwrap();
+        assert_eq!(filenames(&index, "repo:billing").len(), 2);
+    }
+
+    #[test]
+    fn test_index_branch() {
+        if !has_git() {
+            return;
+        }
+        let fixture = Fixture::new();
+        let repo = fixture.write_files("repo", &[]);
+        let loc = fixture.path("index");
+        git(&repo, &["init", "-q", "-b", "main"]);
+        fs::write(format!("{}/shared.go", repo), "func shared() {}\n").unwrap();
+        fs::write(format!("{}/pay.go", repo), "func pay() {}\n").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "main"]);
+        git(&repo, &["checkout", "-q", "-b", "dev"]);
+        fs::write(format!("{}/pay.go", repo), "func pay(amount int) {}\n").unwrap();
+        git(&repo, &["commit", "-q", "-am", "dev"]);
+        // The working tree isn't indexed, only the commits are.
+        fs::write(format!("{}/pay.go", repo), "func uncommitted() {}\n").unwrap();
+
+        let mut index = Index::new(&loc);
+        index
+            .index_branch(&repo, "main", &IndexOptions::new())
+            .unwrap();
+        index
+            .index_branch(&repo, "dev", &IndexOptions::new())
+            .unwrap();
+
+        let branches = |index: &Index, query: &str| -> Vec<(String, Vec<String>)> {
+            let mut result: Vec<_> = index
+                .search(QueryNode::new(query))
+                .into_iter()
+                .map(|m| {
+                    let names = m.branches.iter().map(|b| b.name.clone()).collect();
+                    (m.filename, names)
+                })
+                .collect();
+            result.sort();
+            result
         };
-        x.current = x.advance();
-        x
+        let pay = format!("{}/pay.go", repo);
+        let shared = format!("{}/shared.go", repo);
+        assert_eq!(
+            branches(&index, "func"),
+            vec![
+                (pay.clone(), vec!["dev".to_string()]),
+                (pay.clone(), vec!["main".to_string()]),
+                (shared.clone(), vec!["dev".to_string(), "main".to_string()]),
+            ]
+        );
+        assert_eq!(branches(&index, "amount AND branch:main"), vec![]);
+        assert_eq!(branches(&index, "amount AND branch:dev").len(), 1);
+        assert!(branches(&index, "uncommitted").is_empty());
+
+        let matches = index.search(QueryNode::new("shared"));
+        let commit = |rev: &str| git::resolve(Path::new(&repo), rev).unwrap();
+        assert_eq!(
+            matches[0].branches,
+            vec![
+                Branch {
+                    name: "dev".into(),
+                    commit: commit("dev"),
+                },
+                Branch {
+                    name: "main".into(),
+                    commit: commit("main"),
+                },
+            ]
+        );
+
+        // Files that left the branch are removed, unless another branch has them.
+        git(&repo, &["checkout", "-q", "-f", "main"]);
+        git(&repo, &["rm", "-q", "shared.go", "pay.go"]);
+        fs::write(format!("{}/new.go", repo), "func added() {}\n").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "remove"]);
+        index
+            .index_branch(&repo, "main", &IndexOptions::new())
+            .unwrap();
+        assert_eq!(
+            branches(&index, "func"),
+            vec![
+                (format!("{}/new.go", repo), vec!["main".to_string()]),
+                (pay, vec!["dev".to_string()]),
+                (shared, vec!["dev".to_string()]),
+            ]
+        );
+        assert!(index
+            .index_branch(&repo, "missing", &IndexOptions::new())
+            .is_err());
     }
-}
 
-impl And {
-    fn advance(&mut self) -> Option<FileId> {
-        'outer: loop {
-            let current = self.iterators.first_mut().and_then(|i| i.next());
-            current.as_ref()?;
-            let current = current.unwrap();
-            for iterator in self.iterators.iter_mut().skip(1) {
-                if !iterator.matches(&current) {
-                    continue 'outer;
-                }
-            }
-            return Some(current);
+    #[test]
+    fn test_index_branch_subdirectory() {
+        if !has_git() {
+            return;
         }
+        let fixture = Fixture::new();
+        let repo = fixture.write_files(
+            "repo",
+            &[
+                ("README.md", "func readme() {}\n"),
+                ("payments/pay.go", "func pay() {}\n"),
+                ("payments/api/charge.go", "func charge() {}\n"),
+            ],
+        );
+        git(&repo, &["init", "-q", "-b", "main"]);
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "main"]);
+
+        // Only the files under the subdirectory are indexed, at their paths.
+        let sub = format!("{}/payments", repo);
+        let mut index = Index::new(&fixture.path("index"));
+        index
+            .index_branch(&sub, "main", &IndexOptions::new())
+            .unwrap();
+        assert_eq!(
+            filenames(&index, "func"),
+            vec![format!("{}/api/charge.go", sub), format!("{}/pay.go", sub)]
+        );
     }
-}
 
-impl MatchIter for And {
-    fn matches(&self, fid: &FileId) -> bool {
-        self.iterators.iter().all(|i| i.matches(fid))
+    #[test]
+    fn test_index_branch_ignore_file() {
+        if !has_git() {
+            return;
+        }
+        let fixture = Fixture::new();
+        let repo = fixture.write_files(
+            "repo",
+            &[
+                (".puffinignore", "vendor/\n*.gen.go\n"),
+                ("pay.go", "func pay() {}\n"),
+                ("pay.gen.go", "func generated() {}\n"),
+                ("vendor/lib.go", "func vendored() {}\n"),
+                ("api/.puffinignore", "!*.gen.go\n"),
+                ("api/charge.gen.go", "func charge() {}\n"),
+            ],
+        );
+        git(&repo, &["init", "-q", "-b", "main"]);
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "main"]);
+
+        let mut index = Index::new(&fixture.path("index"));
+        index
+            .index_branch(&repo, "main", &IndexOptions::new())
+            .unwrap();
+        assert_eq!(
+            filenames(&index, "func"),
+            vec![
+                format!("{}/api/charge.gen.go", repo),
+                format!("{}/pay.go", repo)
+            ]
+        );
     }
 
-    fn next(&mut self) -> Option<FileId> {
-        let current = self.current.take();
-        self.current = self.advance();
-        current
+    #[test]
+    fn test_index_history() {
+        if !has_git() {
+            return;
+        }
+        let fixture = Fixture::new();
+        let repo = fixture.write_files("repo", &[]);
+        let loc = fixture.path("index");
+        let pay = format!("{}/pay.go", repo);
+        git(&repo, &["init", "-q", "-b", "main"]);
+        fs::write(&pay, "func pay() {}\n").unwrap();
+        git(&repo, &["add", "."]);
+        git(
+            &repo,
+            &[
+                "commit",
+                "-q",
+                "-m",
+                "Add payments",
+                "--author=Alice <alice@example.com>",
+                "--date=2023-05-01T12:00:00Z",
+            ],
+        );
+        fs::write(&pay, "func pay() {\n\tretryLimit := 3\n}\n").unwrap();
+        git(
+            &repo,
+            &[
+                "commit",
+                "-q",
+                "-am",
+                "Retry failed payments\n\nFixes the outage.",
+                "--author=Bob <bob@example.com>",
+                "--date=2024-02-10T12:00:00Z",
+            ],
+        );
+
+        let mut index = Index::new(&loc);
+        index
+            .index_history(&repo, "main", &IndexOptions::new())
+            .unwrap();
+        index.index(&repo).unwrap();
+
+        let matches = |index: &Index, query: &str| -> Vec<(DocumentKind, String)> {
+            let mut result: Vec<_> = index
+                .search(QueryNode::new(query))
+                .into_iter()
+                .map(|m| (m.kind, m.commit.map(|c| c.subject).unwrap_or_default()))
+                .collect();
+            result.sort();
+            result
+        };
+        // History documents are only searched when asked for.
+        assert_eq!(
+            matches(&index, "retryLimit"),
+            vec![(DocumentKind::File, String::new())]
+        );
+        assert_eq!(
+            matches(&index, "type:diff AND retryLimit"),
+            vec![(DocumentKind::Diff, "Retry failed payments".into())]
+        );
+        assert_eq!(
+            matches(&index, "type:commit AND outage"),
+            vec![(DocumentKind::Commit, "Retry failed payments".into())]
+        );
+        assert_eq!(
+            matches(&index, "author:bob"),
+            vec![
+                (DocumentKind::Commit, "Retry failed payments".into()),
+                (DocumentKind::Diff, "Retry failed payments".into()),
+            ]
+        );
+        assert_eq!(
+            matches(&index, "type:diff AND before:2024-01-01"),
+            vec![(DocumentKind::Diff, "Add payments".into())]
+        );
+        assert_eq!(
+            matches(&index, "type:commit AND after:2024-02-10"),
+            vec![(DocumentKind::Commit, "Retry failed payments".into())]
+        );
+
+        let diff = index.search(QueryNode::new("type:diff AND retryLimit"));
+        assert_eq!(diff[0].filename, pay);
+        let lines: Vec<&str> = diff[0]
+            .line_matches
+            .iter()
+            .map(|l| l.line.as_str())
+            .collect();
+        assert_eq!(lines, vec!["+\tretryLimit := 3"]);
+
+        // Only new commits are indexed again, the history survives reopening.
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "Empty"]);
+        index
+            .index_history(&repo, "main", &IndexOptions::new())
+            .unwrap();
+        index.flush().unwrap();
+        let index = Index::open(&loc).unwrap();
+        assert_eq!(matches(&index, "type:commit").len(), 3);
+        assert_eq!(matches(&index, "author:/^alice/").len(), 2);
+        assert_eq!(matches(&index, "NOT type:file").len(), 5);
+        let stats = index.stats();
+        assert_eq!((stats.files, stats.commits, stats.diffs), (1, 3, 2));
+        assert_eq!(stats.languages, BTreeMap::from([("Go".to_string(), 1)]));
     }
-}
 
-struct ContentGrams(Vec<FileId>);
-
-impl ContentGrams {
-    pub fn new(q: String, index: &Index) -> Self {
-        let mut matching_file_ids: BTreeSet<FileId> = BTreeSet::new();
-        for (trigram, _) in split_ngrams(&q) {
-            let mut set: BTreeSet<FileId> = BTreeSet::new();
-
-            if let Some(files) = index.content_ngrams.get(trigram.to_string().as_str()) {
-                set.clear();
-                set.extend(files.0.clone());
-                if matching_file_ids.is_empty() {
-                    matching_file_ids = set;
-                } else {
-                    matching_file_ids = matching_file_ids.intersection(&set).cloned().collect();
-                }
-            }
+    #[test]
+    fn test_index_history_subdirectory() {
+        if !has_git() {
+            return;
         }
+        let fixture = Fixture::new();
+        let repo = fixture.write_files(
+            "repo",
+            &[
+                ("README.md", "retryLimit docs\n"),
+                ("payments/pay.go", "retryLimit := 3\n"),
+            ],
+        );
+        git(&repo, &["init", "-q", "-b", "main"]);
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "Add retries"]);
 
-        Self(matching_file_ids.into_iter().rev().collect())
+        // Only the changes under the subdirectory are indexed, at their paths.
+        let sub = format!("{}/payments", repo);
+        let mut index = Index::new(&fixture.path("index"));
+        index
+            .index_history(&sub, "main", &IndexOptions::new())
+            .unwrap();
+        assert_eq!(
+            filenames(&index, "type:diff AND retryLimit"),
+            vec![format!("{}/pay.go", sub)]
+        );
     }
-}
 
-impl MatchIter for ContentGrams {
-    fn matches(&self, fid: &FileId) -> bool {
-        self.0.contains(fid)
+    fn read_dir_files(dir: &str) -> BTreeMap<String, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            if path.is_dir() {
+                for (sub, bytes) in read_dir_files(path.to_str().unwrap()) {
+                    files.insert(format!("{}/{}", name, sub), bytes);
+                }
+            } else {
+                files.insert(name, fs::read(&path).unwrap());
+            }
+        }
+        files
     }
 
-    fn next(&mut self) -> Option<FileId> {
-        self.0.pop()
+    #[test]
+    fn test_deterministic_index() {
+        let fixture = Fixture::new();
+        for i in 0..200 {
+            fixture.write(
+                &format!("src/mod{}/file{}.rs", i % 7, i),
+                format!(
+                    "fn function_{}() -> u32 {{\n    {} * {}\n}}\n",
+                    i,
+                    i,
+                    i % 13
+                ),
+            );
+        }
+        let src = fixture.path("src");
+
+        let mut outputs = Vec::new();
+        for threads in [1, 4] {
+            let loc = fixture.path(&format!("index{}", threads));
+            let shard = fixture.path(&format!("shard{}", threads));
+            let mut index = Index::with_positions(&loc);
+            index
+                .index_with_options(&src, &IndexOptions::new().threads(threads))
+                .unwrap();
+            index.flush().unwrap();
+            index.write_shard(&shard).unwrap();
+            outputs.push((read_dir_files(&loc), fs::read(&shard).unwrap()));
+        }
+        assert!(!outputs[0].0.is_empty());
+        assert!(outputs[0] == outputs[1]);
     }
 }
@@ -0,0 +1,332 @@
+use crate::FileId;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// MatchIter iterates over matching documents in ascending order of their ids.
+///
+/// Every iterator keeps track of its current document, i.e. the document it
+/// returned last. Once an iterator returns `None` it is exhausted and keeps
+/// returning `None`.
+pub(crate) trait MatchIter {
+    /// Advances to the next matching document.
+    fn next(&mut self) -> Option<FileId>;
+
+    /// Advances to the first matching document greater or equal to `target`.
+    /// Never moves backwards; if the current document is already greater or
+    /// equal to `target` it is returned again.
+    fn seek(&mut self, target: &FileId) -> Option<FileId>;
+}
+
+/// Postings iterates over a sorted list of document ids.
+pub(crate) struct Postings {
+    ids: Vec<FileId>,
+    /// Index of the current document, `None` before the first call.
+    pos: Option<usize>,
+}
+
+impl Postings {
+    pub fn new(ids: impl IntoIterator<Item = FileId>) -> Self {
+        let ids: Vec<FileId> = ids.into_iter().collect();
+        debug_assert!(ids.windows(2).all(|w| w[0] < w[1]), "ids must be sorted");
+        Self { ids, pos: None }
+    }
+}
+
+impl MatchIter for Postings {
+    fn next(&mut self) -> Option<FileId> {
+        let pos = self.pos.map_or(0, |pos| pos + 1).min(self.ids.len());
+        self.pos = Some(pos);
+        self.ids.get(pos).cloned()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        let start = self.pos.unwrap_or(0);
+        // Gallop to find a window containing the target and then binary search
+        // inside of it, so seeking costs O(log(distance)).
+        let mut bound = 1;
+        while start + bound < self.ids.len() && self.ids[start + bound] < *target {
+            bound *= 2;
+        }
+        let lo = (start + bound / 2).min(self.ids.len());
+        let hi = (start + bound + 1).min(self.ids.len());
+        let pos = if self.ids.get(start).map_or(true, |id| id >= target) {
+            start
+        } else {
+            lo + self.ids[lo..hi].partition_point(|id| id < target)
+        };
+        self.pos = Some(pos);
+        self.ids.get(pos).cloned()
+    }
+}
+
+/// Filter yields the documents of the iterator accepted by the predicate.
+/// The predicate is only called for documents that are actually visited, so
+/// expensive checks are skipped for documents never asked for.
+pub(crate) struct Filter<'a> {
+    iterator: Box<dyn MatchIter + 'a>,
+    predicate: Box<dyn Fn(&FileId) -> bool + 'a>,
+    /// Last accepted document, returned again when seeking to it.
+    current: Option<FileId>,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(
+        iterator: Box<dyn MatchIter + 'a>,
+        predicate: impl Fn(&FileId) -> bool + 'a,
+    ) -> Self {
+        Self {
+            iterator,
+            predicate: Box::new(predicate),
+            current: None,
+        }
+    }
+
+    fn accept(&mut self, mut doc: FileId) -> Option<FileId> {
+        while !(self.predicate)(&doc) {
+            doc = self.iterator.next()?;
+        }
+        self.current = Some(doc.clone());
+        Some(doc)
+    }
+}
+
+impl MatchIter for Filter<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        let doc = self.iterator.next();
+        self.current = None;
+        self.accept(doc?)
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        if let Some(current) = self.current.as_ref().filter(|&current| current >= target) {
+            return Some(current.clone());
+        }
+        let doc = self.iterator.seek(target);
+        self.current = None;
+        self.accept(doc?)
+    }
+}
+
+/// Not yields every document of the universe that the excluded iterator doesn't.
+pub(crate) struct Not<'a> {
+    universe: Box<dyn MatchIter + 'a>,
+    excluded: Box<dyn MatchIter + 'a>,
+}
+
+impl<'a> Not<'a> {
+    pub fn new(universe: Box<dyn MatchIter + 'a>, excluded: Box<dyn MatchIter + 'a>) -> Self {
+        Self { universe, excluded }
+    }
+
+    fn skip_excluded(&mut self, mut doc: FileId) -> Option<FileId> {
+        while self.excluded.seek(&doc).as_ref() == Some(&doc) {
+            doc = self.universe.next()?;
+        }
+        Some(doc)
+    }
+}
+
+impl MatchIter for Not<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        let doc = self.universe.next()?;
+        self.skip_excluded(doc)
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        let doc = self.universe.seek(target)?;
+        self.skip_excluded(doc)
+    }
+}
+
+/// Or yields the union of its iterators, merged through a min-heap.
+pub(crate) struct Or<'a> {
+    iterators: Vec<Box<dyn MatchIter + 'a>>,
+    /// Current document of every iterator that isn't exhausted yet.
+    heap: BinaryHeap<Reverse<(FileId, usize)>>,
+    current: Option<FileId>,
+    started: bool,
+}
+
+impl<'a> Or<'a> {
+    pub fn new(iterators: Vec<Box<dyn MatchIter + 'a>>) -> Self {
+        Self {
+            heap: BinaryHeap::with_capacity(iterators.len()),
+            iterators,
+            current: None,
+            started: false,
+        }
+    }
+
+    /// Moves every iterator whose current document is below `target` forward.
+    fn advance(&mut self, target: &FileId, inclusive: bool) -> Option<FileId> {
+        while let Some(Reverse((doc, i))) = self.heap.peek().cloned() {
+            if doc > *target || (inclusive && doc == *target) {
+                break;
+            }
+            self.heap.pop();
+            let next = if inclusive {
+                self.iterators[i].seek(target)
+            } else {
+                self.iterators[i].next()
+            };
+            if let Some(next) = next {
+                self.heap.push(Reverse((next, i)));
+            }
+        }
+        self.current = self.heap.peek().map(|Reverse((doc, _))| doc.clone());
+        self.current.clone()
+    }
+
+    fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            for (i, iterator) in self.iterators.iter_mut().enumerate() {
+                if let Some(doc) = iterator.next() {
+                    self.heap.push(Reverse((doc, i)));
+                }
+            }
+        }
+    }
+}
+
+impl MatchIter for Or<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        if !self.started {
+            self.start();
+            self.current = self.heap.peek().map(|Reverse((doc, _))| doc.clone());
+            return self.current.clone();
+        }
+        let current = self.current.clone()?;
+        self.advance(&current, false)
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        self.start();
+        match &self.current {
+            Some(current) if current >= target => Some(current.clone()),
+            _ => self.advance(target, true),
+        }
+    }
+}
+
+/// And yields the intersection of its iterators using leapfrog joins: every
+/// iterator seeks to the largest document seen so far until all agree.
+pub(crate) struct And<'a> {
+    iterators: Vec<Box<dyn MatchIter + 'a>>,
+}
+
+impl<'a> And<'a> {
+    pub fn new(iterators: Vec<Box<dyn MatchIter + 'a>>) -> Self {
+        Self { iterators }
+    }
+
+    fn leapfrog(&mut self, mut target: FileId) -> Option<FileId> {
+        'outer: loop {
+            for iterator in self.iterators.iter_mut() {
+                let doc = iterator.seek(&target)?;
+                if doc != target {
+                    target = doc;
+                    continue 'outer;
+                }
+            }
+            return Some(target);
+        }
+    }
+}
+
+impl MatchIter for And<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        let target = self.iterators.first_mut()?.next()?;
+        self.leapfrog(target)
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        let target = self.iterators.first_mut()?.seek(target)?;
+        self.leapfrog(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matchtree::*;
+
+    fn postings(ids: &[u32]) -> Box<dyn MatchIter> {
+        Box::new(Postings::new(ids.iter().map(|&id| FileId(id))))
+    }
+
+    fn collect(mut iter: impl MatchIter) -> Vec<u32> {
+        let mut result = Vec::new();
+        while let Some(FileId(id)) = iter.next() {
+            result.push(id);
+        }
+        result
+    }
+
+    #[test]
+    fn test_postings_seek() {
+        let mut p = Postings::new((0..100).map(|id| FileId(id * 2)));
+        assert_eq!(p.seek(&FileId(0)), Some(FileId(0)));
+        assert_eq!(p.seek(&FileId(0)), Some(FileId(0)));
+        assert_eq!(p.next(), Some(FileId(2)));
+        assert_eq!(p.seek(&FileId(51)), Some(FileId(52)));
+        assert_eq!(p.seek(&FileId(10)), Some(FileId(52)));
+        assert_eq!(p.next(), Some(FileId(54)));
+        assert_eq!(p.seek(&FileId(198)), Some(FileId(198)));
+        assert_eq!(p.seek(&FileId(199)), None);
+        assert_eq!(p.next(), None);
+    }
+
+    #[test]
+    fn test_and() {
+        let and = And::new(vec![
+            postings(&[1, 2, 3, 5, 8, 13, 21]),
+            postings(&[2, 3, 4, 5, 6, 7, 8, 21]),
+            postings(&[0, 3, 8, 21, 34]),
+        ]);
+        assert_eq!(collect(and), vec![3, 8, 21]);
+    }
+
+    #[test]
+    fn test_or() {
+        let or = Or::new(vec![
+            postings(&[1, 5, 9]),
+            postings(&[]),
+            postings(&[2, 5, 10]),
+        ]);
+        assert_eq!(collect(or), vec![1, 2, 5, 9, 10]);
+
+        let mut or = Or::new(vec![postings(&[1, 5, 9]), postings(&[2, 5, 10])]);
+        assert_eq!(or.seek(&FileId(3)), Some(FileId(5)));
+        assert_eq!(or.next(), Some(FileId(9)));
+    }
+
+    #[test]
+    fn test_not() {
+        let not = Not::new(postings(&[1, 2, 3, 4, 5, 6]), postings(&[2, 3, 6, 7]));
+        assert_eq!(collect(not), vec![1, 4, 5]);
+    }
+
+    #[test]
+    fn test_filter() {
+        let visited = std::cell::RefCell::new(Vec::new());
+        let mut filter = Filter::new(postings(&[1, 2, 3, 4, 5, 6, 7, 8]), |FileId(id)| {
+            visited.borrow_mut().push(*id);
+            id % 2 == 0
+        });
+        assert_eq!(filter.seek(&FileId(3)), Some(FileId(4)));
+        assert_eq!(filter.seek(&FileId(4)), Some(FileId(4)));
+        assert_eq!(filter.next(), Some(FileId(6)));
+        drop(filter);
+        assert_eq!(visited.into_inner(), vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_nested() {
+        // (a OR b) AND NOT c
+        let tree = And::new(vec![
+            Box::new(Or::new(vec![postings(&[1, 3, 5]), postings(&[2, 3, 6])])),
+            Box::new(Not::new(postings(&[1, 2, 3, 4, 5, 6]), postings(&[3, 5]))),
+        ]);
+        assert_eq!(collect(tree), vec![1, 2, 6]);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Metadata is an in-memory map of prost encoded records, persisted as a
+/// single file. Keys are decoded with `TryFrom`, so a corrupted key fails the
+/// load instead of panicking.
+pub struct Metadata<K, V> {
+    path: PathBuf,
+    data: BTreeMap<K, V>,
+}
+
+impl<K, V> Metadata<K, V>
+where
+    K: Ord + Clone + TryFrom<Vec<u8>, Error = io::Error> + Into<Vec<u8>>,
+    V: prost::Message + Default,
+{
+    /// Creates an empty store that gets written to `path` on flush.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            data: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the records stored in `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path.as_ref())?.read_to_end(&mut bytes)?;
+
+        let mut metadata = Self::new(path);
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let key_len = rest
+                .get(0..4)
+                .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+                .ok_or_else(|| invalid_data("truncated metadata key"))?;
+            let key = rest
+                .get(4..4 + key_len)
+                .ok_or_else(|| invalid_data("truncated metadata key"))?;
+            let key = K::try_from(key.to_vec())?;
+            rest = &rest[4 + key_len..];
+            let value = V::decode_length_delimited(&mut rest)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            metadata.data.insert(key, value);
+        }
+        Ok(metadata)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.data.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.data.get_mut(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.data.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.data.remove(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.data.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.data.iter()
+    }
+
+    /*
+    Metadata layout, ordered by key:
+    [key len][key][value len][value]...
+    <-4 byte-><len><-varint-><-len->
+    */
+    pub fn flush(&self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let mut buf = Vec::new();
+        for (key, value) in self.data.iter() {
+            let key: Vec<u8> = key.clone().into();
+            writer.write_all(&u32::to_le_bytes(key.len() as u32))?;
+            writer.write_all(&key)?;
+            buf.clear();
+            value.encode_length_delimited(&mut buf)?;
+            writer.write_all(&buf)?;
+        }
+        writer.flush()?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::*;
+    use crate::search;
+    use crate::testutil::Fixture;
+    use crate::FileId;
+
+    #[test]
+    fn test_round_trip() {
+        let fixture = Fixture::new();
+        let path = fixture.path("metadata");
+
+        let mut metadata = Metadata::new(&path);
+        for (id, filename) in [(3, "src/lib.rs"), (1, "src/main.rs")] {
+            metadata.insert(
+                FileId(id),
+                search::File {
+                    filename: filename.into(),
+                    content: format!("// {}\n", filename),
+                    file_type: "Rust".into(),
+                    ..Default::default()
+                },
+            );
+        }
+        metadata.flush().unwrap();
+
+        let reopened: Metadata<FileId, search::File> = Metadata::open(&path).unwrap();
+        assert_eq!(
+            reopened.keys().cloned().collect::<Vec<_>>(),
+            vec![FileId(1), FileId(3)]
+        );
+        assert_eq!(
+            reopened.get(&FileId(3)).map(|f| f.content.as_str()),
+            Some("// src/lib.rs\n")
+        );
+    }
+
+    #[test]
+    fn test_corrupted_key() {
+        let fixture = Fixture::new();
+        // A two byte key followed by an empty record.
+        let path = fixture.write("metadata", [2, 0, 0, 0, 1, 0, 0]);
+
+        let err = Metadata::<FileId, search::File>::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
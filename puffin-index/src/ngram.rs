@@ -9,7 +9,7 @@ const RUNE_MASK: u64 = (1 << 21) - 1;
 /// We use u64 under the hood for optimization while keeping enough bytes to account
 /// for UNICODE characters.
 // Heavily inspired by https://github.com/sourcegraph/zoekt.
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Ngram(u64);
 
 impl Ngram {
@@ -83,12 +83,30 @@ impl From<[char; NGRAM_SIZE]> for Ngram {
     }
 }
 
+/// Folds the char using Unicode simple case folding, so that all case variants
+/// of a char map to the same one. Chars that only fold into multiple chars,
+/// such as 'ß', are kept as they are.
+pub fn fold_case(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    let upper = match (upper.next(), upper.next()) {
+        (Some(upper), None) => upper,
+        _ => c,
+    };
+    let mut lower = upper.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => upper,
+    }
+}
+
+/// Splits the string into case folded n-grams, along with their positions.
+/// Folding keeps one char per char, so positions match the original string.
 pub fn split_ngrams(s: &str) -> Vec<(Ngram, u64)> {
     let mut rune_gram = ['\0'; NGRAM_SIZE];
     let mut rune_count = 0;
     let mut result = Vec::new();
 
-    for r in s.chars() {
+    for r in s.chars().map(fold_case) {
         rune_gram[0] = rune_gram[1];
         rune_gram[1] = rune_gram[2];
         rune_gram[2] = r;
@@ -131,8 +149,26 @@ mod tests {
     #[test]
     fn test_split_ngrams() {
         assert_eq!(
-            vec![(Ngram::from("Tes"), 0), (Ngram::from("est"), 1),],
+            vec![(Ngram::from("tes"), 0), (Ngram::from("est"), 1),],
             split_ngrams("Test")
         );
     }
+
+    #[test]
+    fn test_fold_case() {
+        for (c, folded) in [
+            ('a', 'a'),
+            ('A', 'a'),
+            ('É', 'é'),
+            ('Σ', 'σ'),
+            ('ς', 'σ'),
+            ('ſ', 's'),
+            ('\u{212a}', 'k'),
+            ('ß', 'ß'),
+            ('İ', 'İ'),
+            ('_', '_'),
+        ] {
+            assert_eq!(fold_case(c), folded, "{:?}", c);
+        }
+    }
 }
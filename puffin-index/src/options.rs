@@ -0,0 +1,253 @@
+use crate::search;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Name of the ignore files honored next to `.gitignore`, using the same syntax.
+pub const IGNORE_FILENAME: &str = ".puffinignore";
+
+/// IndexOptions controls which files of a directory get indexed.
+///
+/// ```
+/// use puffin_index::IndexOptions;
+///
+/// let options = IndexOptions::new()
+///     .max_file_size(1 << 20)
+///     .repository("payments")
+///     .ignore("target/")
+///     .include("*.rs");
+/// ```
+#[derive(Clone, Debug)]
+pub struct IndexOptions {
+    pub(crate) max_file_size: u64,
+    pub(crate) max_file_count: Option<usize>,
+    pub(crate) hidden: bool,
+    pub(crate) follow_links: bool,
+    pub(crate) ignores: Vec<String>,
+    pub(crate) includes: Vec<String>,
+    pub(crate) excludes: Vec<String>,
+    pub(crate) threads: Option<usize>,
+    pub(crate) repository: Option<String>,
+    pub(crate) url_template: Option<String>,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        Self {
+            max_file_size: 2 << 20,
+            max_file_count: None,
+            hidden: false,
+            follow_links: false,
+            ignores: Vec::new(),
+            includes: Vec::new(),
+            excludes: Vec::new(),
+            threads: None,
+            repository: None,
+            url_template: None,
+        }
+    }
+}
+
+impl IndexOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Files larger than this many bytes are skipped without being read.
+    /// Defaults to 2 MiB.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Indexes at most this many files, taken in the order of their paths.
+    pub fn max_file_count(mut self, count: usize) -> Self {
+        self.max_file_count = Some(count);
+        self
+    }
+
+    /// Whether hidden files and directories are indexed. Defaults to false.
+    pub fn hidden(mut self, yes: bool) -> Self {
+        self.hidden = yes;
+        self
+    }
+
+    /// Whether symbolic links are followed. Defaults to false.
+    pub fn follow_links(mut self, yes: bool) -> Self {
+        self.follow_links = yes;
+        self
+    }
+
+    /// Adds a line in the `.gitignore` syntax, applied on top of the ignore
+    /// files found in the directory.
+    pub fn ignore(mut self, glob: &str) -> Self {
+        self.ignores.push(glob.to_string());
+        self
+    }
+
+    /// Only files matching one of the include patterns are indexed, if any
+    /// are given. Patterns are globs matched against paths relative to the
+    /// indexed directory.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.includes.push(pattern.to_string());
+        self
+    }
+
+    /// Files matching the pattern are never indexed, even if they match one
+    /// of the include patterns.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.excludes.push(pattern.to_string());
+        self
+    }
+
+    /// Number of threads walking and tokenizing the files. Defaults to the
+    /// available parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Name of the repository the files belong to. Defaults to the name of
+    /// the indexed directory.
+    pub fn repository(mut self, name: &str) -> Self {
+        self.repository = Some(name.to_string());
+        self
+    }
+
+    /// URL of the files in the repository, such as
+    /// `https://github.com/org/repo/blob/main/{path}#L{line}`. `{path}` is
+    /// replaced by the path relative to the indexed directory and `{line}` by
+    /// the number of the first matching line.
+    pub fn url_template(mut self, template: &str) -> Self {
+        self.url_template = Some(template.to_string());
+        self
+    }
+
+    /// Describes the repository rooted at the indexed directory.
+    pub(crate) fn repository_at(&self, root: &str) -> search::Repository {
+        let name = self.repository.clone().unwrap_or_else(|| {
+            Path::new(root)
+                .canonicalize()
+                .ok()
+                .and_then(|path| Some(path.file_name()?.to_str()?.to_string()))
+                .unwrap_or_else(|| root.to_string())
+        });
+        search::Repository {
+            name,
+            root: root.to_string(),
+            url_template: self.url_template.clone().unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn thread_count(&self) -> usize {
+        self.threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+            .max(1)
+    }
+
+    /// Builds the matcher of the extra ignore lines, rooted at the directory.
+    pub(crate) fn ignore_matcher(&self, root: &Path) -> io::Result<Gitignore> {
+        let mut builder = GitignoreBuilder::new(root);
+        for glob in self.ignores.iter() {
+            builder.add_line(None, glob).map_err(invalid_input)?;
+        }
+        builder.build().map_err(invalid_input)
+    }
+
+    /// Builds the matcher of the include and exclude patterns.
+    pub(crate) fn overrides(&self, root: &Path) -> io::Result<Override> {
+        let mut builder = OverrideBuilder::new(root);
+        for pattern in self.includes.iter() {
+            builder.add(pattern).map_err(invalid_input)?;
+        }
+        for pattern in self.excludes.iter() {
+            builder
+                .add(&format!("!{}", pattern))
+                .map_err(invalid_input)?;
+        }
+        builder.build().map_err(invalid_input)
+    }
+}
+
+/// SearchOptions limits the size and the cost of search results. There are
+/// no limits by default.
+///
+/// ```
+/// use puffin_index::SearchOptions;
+/// use std::time::Duration;
+///
+/// let options = SearchOptions::new()
+///     .max_files(50)
+///     .max_line_matches(5)
+///     .context_lines(2)
+///     .time_budget(Duration::from_millis(500));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    pub(crate) max_files: Option<usize>,
+    pub(crate) max_matches: Option<usize>,
+    pub(crate) max_line_matches: Option<usize>,
+    pub(crate) time_budget: Option<Duration>,
+    pub(crate) context_lines: usize,
+    pub(crate) threads: Option<usize>,
+}
+
+impl SearchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns at most this many files, the ones with the highest scores.
+    /// Scores are only known once a file is evaluated, so every matching file
+    /// still is. Only `max_matches` and `time_budget` stop the evaluation
+    /// early.
+    pub fn max_files(mut self, count: usize) -> Self {
+        self.max_files = Some(count);
+        self
+    }
+
+    /// Stops evaluating the query once the matching files have this many
+    /// matches in total. Only the files found until then are ranked.
+    pub fn max_matches(mut self, count: usize) -> Self {
+        self.max_matches = Some(count);
+        self
+    }
+
+    /// Keeps at most this many matching lines of every file.
+    pub fn max_line_matches(mut self, count: usize) -> Self {
+        self.max_line_matches = Some(count);
+        self
+    }
+
+    /// Stops evaluating the query once it ran for this long.
+    pub fn time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Number of lines around every matching line returned along with it.
+    /// Defaults to none.
+    pub fn context_lines(mut self, count: usize) -> Self {
+        self.context_lines = count;
+        self
+    }
+
+    /// Number of threads searching the shards of a `ShardedIndex`. Defaults
+    /// to the available parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub(crate) fn thread_count(&self) -> usize {
+        self.threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+            .max(1)
+    }
+}
+
+fn invalid_input(err: ignore::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
@@ -0,0 +1,403 @@
+use crate::builder::IndexBuilder;
+use crate::git::{self, BlobReader};
+use crate::ngram::split_ngrams;
+use crate::options::{IndexOptions, IGNORE_FILENAME};
+use crate::positions::{self, Positions};
+use crate::reader::Grams;
+use crate::search::skipped_file::Reason;
+use crate::{encoding, lang, symbols};
+use crate::{search, FileId};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{WalkBuilder, WalkState};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// What happened to a single file when it was processed by a tokenizer.
+pub(crate) enum Update {
+    /// Same mtime and size as the indexed file.
+    Unchanged,
+    /// Same content as the indexed file, with a new mtime or size.
+    Touched { mtime: u64, size: u64 },
+    /// New or changed file, along with its canonical language name.
+    Indexed(Box<search::File>, Option<&'static str>),
+    /// The file isn't indexed, for the given reason.
+    Skipped(Reason),
+}
+
+/// Batch holds the trigram to file maps built by a single tokenizer thread.
+/// The n-gram maps are handed over to the `IndexBuilder` once they grow too
+/// large, and when the thread is done.
+pub(crate) struct Batch {
+    content_ngrams: BTreeMap<String, Vec<FileId>>,
+    path_ngrams: BTreeMap<String, Vec<FileId>>,
+    symbol_ngrams: BTreeMap<String, Vec<FileId>>,
+    /// Rough number of bytes held by the n-gram maps.
+    size: usize,
+    pub positions: Option<BTreeMap<String, Positions>>,
+    pub updates: Vec<(FileId, Update)>,
+}
+
+impl Batch {
+    fn new(with_positions: bool) -> Self {
+        Self {
+            content_ngrams: BTreeMap::new(),
+            path_ngrams: BTreeMap::new(),
+            symbol_ngrams: BTreeMap::new(),
+            size: 0,
+            positions: with_positions.then(BTreeMap::new),
+            updates: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, file_id: &FileId, update: Update) {
+        if let Update::Indexed(file, _) = &update {
+            for line in file.content.lines() {
+                for (trigram, _) in split_ngrams(line) {
+                    self.size +=
+                        push_posting(&mut self.content_ngrams, trigram.to_string(), file_id);
+                }
+            }
+            for (trigram, _) in split_ngrams(&file.filename) {
+                self.size += push_posting(&mut self.path_ngrams, trigram.to_string(), file_id);
+            }
+            for symbol in file.symbols.iter() {
+                for (trigram, _) in split_ngrams(&symbol.name) {
+                    self.size +=
+                        push_posting(&mut self.symbol_ngrams, trigram.to_string(), file_id);
+                }
+            }
+            if let Some(table) = self.positions.as_mut() {
+                for (trigram, offsets) in positions::collect_positions(&file.content) {
+                    table
+                        .entry(trigram)
+                        .or_default()
+                        .insert(file_id.clone(), offsets);
+                }
+            }
+        }
+        self.updates.push((file_id.clone(), update));
+    }
+
+    /// Moves the collected n-grams into the builder.
+    fn drain_into(&mut self, builder: &Mutex<IndexBuilder>) -> io::Result<()> {
+        let mut builder = builder.lock().unwrap();
+        for (trigram, ids) in std::mem::take(&mut self.content_ngrams) {
+            builder.add(Grams::Content, &trigram, &ids)?;
+        }
+        for (trigram, ids) in std::mem::take(&mut self.path_ngrams) {
+            builder.add(Grams::Path, &trigram, &ids)?;
+        }
+        for (trigram, ids) in std::mem::take(&mut self.symbol_ngrams) {
+            builder.add(Grams::Symbol, &trigram, &ids)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn merge(&mut self, other: Batch) {
+        if let (Some(table), Some(other)) = (self.positions.as_mut(), other.positions) {
+            for (trigram, positions) in other {
+                table.entry(trigram).or_default().merge(positions);
+            }
+        }
+        self.updates.extend(other.updates);
+    }
+}
+
+/// Adds the file to the posting list, returning the number of bytes added.
+fn push_posting(
+    table: &mut BTreeMap<String, Vec<FileId>>,
+    trigram: String,
+    file_id: &FileId,
+) -> usize {
+    let mut size = 0;
+    let ids = table.entry(trigram).or_insert_with_key(|trigram| {
+        size += trigram.len() + 64;
+        Vec::new()
+    });
+    if ids.last() != Some(file_id) {
+        ids.push(file_id.clone());
+        size += std::mem::size_of::<FileId>();
+    }
+    size
+}
+
+/// Reads the file, unless it is unchanged since it was indexed last time.
+/// Text that isn't UTF-8 is transcoded, binaries are skipped.
+pub(crate) fn process(
+    path: &Path,
+    known: Option<&search::File>,
+    max_file_size: u64,
+    contents: &mut Vec<u8>,
+) -> Update {
+    let Ok(metadata) = fs::metadata(path) else {
+        return Update::Skipped(Reason::Unreadable);
+    };
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |mtime| mtime.as_nanos() as u64);
+    let size = metadata.len();
+    if size > max_file_size {
+        log::info!("skipping {:?}, too large", path);
+        return Update::Skipped(Reason::TooLarge);
+    }
+    if known.is_some_and(|file| file.mtime == mtime && file.size == size) {
+        return Update::Unchanged;
+    }
+
+    log::info!("indexing {:?}", path);
+
+    let Ok(file) = fs::File::open(path) else {
+        return Update::Skipped(Reason::Unreadable);
+    };
+
+    // The file may have grown since its metadata was read.
+    contents.clear();
+    match file.take(max_file_size + 1).read_to_end(contents) {
+        Ok(read) if read as u64 > max_file_size => {
+            log::info!("skipping {:?}, too large", path);
+            return Update::Skipped(Reason::TooLarge);
+        }
+        Ok(_) => {}
+        Err(_) => return Update::Skipped(Reason::Unreadable),
+    };
+
+    let hash = xxh3_64(contents);
+    if known.is_some_and(|file| file.hash == hash) {
+        return Update::Touched { mtime, size };
+    }
+    decode(path, contents, mtime, size, hash)
+}
+
+/// Reads the file out of the git object database.
+pub(crate) fn process_blob(
+    path: &Path,
+    entry: &git::Entry,
+    blobs: &Mutex<BlobReader>,
+    max_file_size: u64,
+    contents: &mut Vec<u8>,
+) -> Update {
+    if entry.size > max_file_size {
+        log::info!("skipping {:?}, too large", path);
+        return Update::Skipped(Reason::TooLarge);
+    }
+
+    log::info!("indexing {:?}", path);
+
+    if let Err(err) = blobs.lock().unwrap().read(&entry.blob, contents) {
+        log::warn!("unable to read {:?}: {}", path, err);
+        return Update::Skipped(Reason::Unreadable);
+    }
+    decode(path, contents, 0, entry.size, xxh3_64(contents))
+}
+
+/// Turns the raw contents of the file into an indexed file, unless it is a
+/// binary.
+pub(crate) fn decode(path: &Path, contents: &[u8], mtime: u64, size: u64, hash: u64) -> Update {
+    let Some(content) = encoding::decode(contents) else {
+        log::info!("skipping {:?}, binary", path);
+        return Update::Skipped(Reason::Binary);
+    };
+    let language = lang::detect(path, &content);
+    Update::Indexed(
+        Box::new(search::File {
+            filename: path.to_str().unwrap().to_string(),
+            symbols: symbols::extract(language, &content),
+            content,
+            file_type: language.map_or("unknown", |l| l.name).into(),
+            mtime,
+            size,
+            hash,
+            // Set by the index, which knows the repository and the branch
+            // being indexed.
+            repository: None,
+            branches: Vec::new(),
+            kind: search::file::Kind::File.into(),
+            commit: None,
+        }),
+        language.map(|l| l.name),
+    )
+}
+
+/// Lists the files in the directory selected by the options, sorted by path,
+/// along with the selected paths that can't be indexed as they aren't UTF-8.
+pub(crate) fn walk(
+    dir_path: &str,
+    options: &IndexOptions,
+) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let root = Path::new(dir_path);
+    let ignores = options.ignore_matcher(root)?;
+    // Applied to the files only, setting overrides on the walker would let
+    // the include patterns pull in hidden and ignored files.
+    let overrides = options.overrides(root)?;
+    let paths = Mutex::new(Vec::new());
+    WalkBuilder::new(dir_path)
+        .standard_filters(true)
+        .hidden(!options.hidden)
+        .follow_links(options.follow_links)
+        .add_custom_ignore_filename(IGNORE_FILENAME)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !ignores.matched(entry.path(), is_dir).is_ignore()
+        })
+        .threads(options.thread_count())
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                match entry {
+                    Ok(entry)
+                        if entry.path().is_file()
+                            && !overrides.matched(entry.path(), false).is_ignore() =>
+                    {
+                        paths.lock().unwrap().push(entry.into_path());
+                    }
+                    Ok(_) => {}
+                    Err(err) => log::warn!("unable to get directory entry: {}", err),
+                }
+                WalkState::Continue
+            })
+        });
+
+    // The walker finds files in any order, the limit applies to the first
+    // paths so that the same files get indexed every time.
+    let (mut paths, invalid): (Vec<PathBuf>, Vec<PathBuf>) = paths
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .partition(|path| path.to_str().is_some());
+    paths.sort();
+    if let Some(max) = options.max_file_count {
+        if paths.len() > max {
+            log::warn!("indexing only {} of {} files", max, paths.len());
+            paths.truncate(max);
+        }
+    }
+    Ok((paths, invalid))
+}
+
+/// Selects the files of a git tree the same way `walk` selects the files of
+/// a directory. The `.gitignore` files are already honored by git, the
+/// `IGNORE_FILENAME` files are read from the tree.
+pub(crate) fn select(
+    root: &Path,
+    mut entries: Vec<git::Entry>,
+    blobs: &mut BlobReader,
+    options: &IndexOptions,
+) -> io::Result<Vec<git::Entry>> {
+    let ignores = options.ignore_matcher(root)?;
+    let tree_ignores = tree_ignores(root, &entries, blobs)?;
+    let overrides = options.overrides(root)?;
+    entries.retain(|entry| {
+        let path = root.join(&entry.path);
+        let hidden = entry.path.split('/').any(|name| name.starts_with('.'));
+        // The deepest ignore file with a matching pattern wins, as in `walk`.
+        let tree_ignored = tree_ignores
+            .iter()
+            .filter(|ignore| path.starts_with(ignore.path()))
+            .map(|ignore| ignore.matched_path_or_any_parents(&path, false))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore());
+        (options.hidden || !hidden)
+            && !tree_ignored
+            && !ignores
+                .matched_path_or_any_parents(&path, false)
+                .is_ignore()
+            && !overrides.matched(&path, false).is_ignore()
+    });
+    if let Some(max) = options.max_file_count {
+        if entries.len() > max {
+            log::warn!("indexing only {} of {} files", max, entries.len());
+            entries.truncate(max);
+        }
+    }
+    Ok(entries)
+}
+
+/// Builds the matchers of the `IGNORE_FILENAME` files of a git tree, deepest
+/// directories first. Invalid patterns are skipped like in `walk`.
+fn tree_ignores(
+    root: &Path,
+    entries: &[git::Entry],
+    blobs: &mut BlobReader,
+) -> io::Result<Vec<Gitignore>> {
+    let mut ignores = Vec::new();
+    let mut contents = Vec::new();
+    for entry in entries.iter() {
+        let path = Path::new(&entry.path);
+        if path.file_name() != Some(IGNORE_FILENAME.as_ref()) {
+            continue;
+        }
+        blobs.read(&entry.blob, &mut contents)?;
+        let mut builder = GitignoreBuilder::new(root.join(path.parent().unwrap()));
+        for line in String::from_utf8_lossy(&contents).lines() {
+            if let Err(err) = builder.add_line(None, line) {
+                log::warn!("invalid pattern in {}: {}", entry.path, err);
+            }
+        }
+        match builder.build() {
+            Ok(ignore) => ignores.push(ignore),
+            Err(err) => log::warn!("unable to read {}: {}", entry.path, err),
+        }
+    }
+    ignores.sort_by_key(|ignore| Reverse(ignore.path().components().count()));
+    Ok(ignores)
+}
+
+/// Tokenizes the files, sorted by their ids, on a pool of threads. Every
+/// file is turned into an update by `process`, given a buffer to read its
+/// contents into. N-grams go into the builder, positions and updates are
+/// merged into the returned batch. Neither depends on the number of threads.
+pub(crate) fn tokenize<T: Sync>(
+    files: &[(FileId, T)],
+    with_positions: bool,
+    options: &IndexOptions,
+    builder: &Mutex<IndexBuilder>,
+    process: impl Fn(&FileId, &T, &mut Vec<u8>) -> Update + Sync,
+) -> io::Result<Batch> {
+    let threads = options.thread_count();
+    // Every thread gets its share of what the builder leaves of the memory
+    // budget for its own n-grams.
+    let thread_budget = builder.lock().unwrap().batch_budget() / threads;
+    let next = AtomicUsize::new(0);
+    let batches = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut batch = Batch::new(with_positions);
+                    let mut contents = Vec::new();
+                    while let Some((file_id, file)) =
+                        files.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        batch.add(file_id, process(file_id, file, &mut contents));
+                        if batch.size > thread_budget {
+                            batch.drain_into(builder)?;
+                        }
+                    }
+                    batch.drain_into(builder)?;
+                    Ok(batch)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("tokenizer thread panicked"))
+            .collect::<io::Result<Vec<Batch>>>()
+    })?;
+
+    let mut merged = Batch::new(with_positions);
+    for batch in batches {
+        merged.merge(batch);
+    }
+    merged.updates.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(merged)
+}
@@ -0,0 +1,190 @@
+use crate::ngram::split_ngrams;
+use crate::FileId;
+use std::collections::BTreeMap;
+use std::io;
+
+/// Positions holds the offsets of a single trigram in every file it occurs in.
+/// Offsets are counted in chars from the start of the file.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub(crate) struct Positions(BTreeMap<FileId, Vec<u32>>);
+
+impl Positions {
+    /// Adds sorted offsets of the trigram in the given file.
+    pub fn insert(&mut self, file_id: FileId, offsets: Vec<u32>) {
+        let current = self.0.entry(file_id).or_default();
+        current.extend(offsets);
+        current.sort_unstable();
+        current.dedup();
+    }
+
+    pub fn merge(&mut self, other: Positions) {
+        for (file_id, offsets) in other.0 {
+            self.insert(file_id, offsets);
+        }
+    }
+
+    pub fn remove(&mut self, file_id: &FileId) {
+        self.0.remove(file_id);
+    }
+
+    pub fn get(&self, file_id: &FileId) -> &[u32] {
+        self.0
+            .get(file_id)
+            .map_or(&[], |offsets| offsets.as_slice())
+    }
+}
+
+/*
+Positions layout:
+[file id][offsets count][offset 0]...[offset N]...
+<-4 byte-><---4 byte----><-4 byte->
+*/
+impl Positions {
+    /// Reads the encoded positions, failing if an entry doesn't fit the bytes.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut result = BTreeMap::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let header = rest
+                .get(0..8)
+                .ok_or_else(|| invalid_data("truncated positions entry"))?;
+            let file_id = FileId(u32::from_le_bytes(header[0..4].try_into().unwrap()));
+            let count = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+            let end = count
+                .checked_mul(4)
+                .and_then(|len| len.checked_add(8))
+                .filter(|end| *end <= rest.len())
+                .ok_or_else(|| invalid_data("truncated positions offsets"))?;
+            let offsets = rest[8..end]
+                .chunks(4)
+                .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+            result.insert(file_id, offsets);
+            rest = &rest[end..];
+        }
+        Ok(Self(result))
+    }
+}
+
+impl From<Vec<u8>> for Positions {
+    /// Treats corrupted positions as empty.
+    fn from(value: Vec<u8>) -> Self {
+        Self::from_bytes(&value).unwrap_or_else(|err| {
+            log::warn!("failed to read positions: {}", err);
+            Self::default()
+        })
+    }
+}
+
+impl From<Positions> for Vec<u8> {
+    fn from(val: Positions) -> Self {
+        let mut bytes = Vec::new();
+        for (file_id, offsets) in val.0 {
+            bytes.extend(u32::to_le_bytes(file_id.0));
+            bytes.extend(u32::to_le_bytes(offsets.len() as u32));
+            bytes.extend(offsets.into_iter().flat_map(u32::to_le_bytes));
+        }
+        bytes
+    }
+}
+
+/// Collects offsets of all trigrams in the file content. Same as the content
+/// n-grams, trigrams never span multiple lines.
+pub(crate) fn collect_positions(src: &str) -> BTreeMap<String, Vec<u32>> {
+    let mut result: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    let mut line_offset = 0;
+    for raw_line in src.split_inclusive('\n') {
+        let line = raw_line.trim_end_matches('\n').trim_end_matches('\r');
+        for (trigram, pos) in split_ngrams(line) {
+            result
+                .entry(trigram.to_string())
+                .or_default()
+                .push(line_offset + pos as u32);
+        }
+        line_offset += raw_line.chars().count() as u32;
+    }
+    result
+}
+
+/// Checks whether the file contains the phrase by looking for an offset at
+/// which all of its trigrams follow each other. `trigrams` are positions of
+/// the trigrams of the phrase, in order.
+pub(crate) fn contains_phrase(trigrams: &[Positions], file_id: &FileId) -> bool {
+    let Some((first, rest)) = trigrams.split_first() else {
+        return false;
+    };
+    first.get(file_id).iter().any(|&start| {
+        rest.iter()
+            .zip(1..)
+            .all(|(positions, i)| positions.get(file_id).binary_search(&(start + i)).is_ok())
+    })
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::positions::*;
+
+    fn positions_of(src: &str, file_id: &FileId, phrase: &str) -> Vec<Positions> {
+        let offsets = collect_positions(src);
+        split_ngrams(phrase)
+            .into_iter()
+            .map(|(trigram, _)| {
+                let mut positions = Positions::default();
+                if let Some(offsets) = offsets.get(&trigram.to_string()) {
+                    positions.insert(file_id.clone(), offsets.clone());
+                }
+                // Round trip through the on-disk encoding.
+                Positions::from(Vec::<u8>::from(positions))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_contains_phrase() {
+        let file_id = FileId(7);
+        let src = "fn foo() {}\r\nfn main() {\n    main()\n}\n";
+
+        assert!(contains_phrase(
+            &positions_of(src, &file_id, "fn main"),
+            &file_id
+        ));
+        assert!(contains_phrase(
+            &positions_of(src, &file_id, "    main()"),
+            &file_id
+        ));
+        // All trigrams are present, just not next to each other.
+        assert!(!contains_phrase(
+            &positions_of(src, &file_id, "fn main() {}"),
+            &file_id
+        ));
+        assert!(!contains_phrase(
+            &positions_of(src, &file_id, "fn main"),
+            &FileId(8)
+        ));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut positions = Positions::default();
+        positions.insert(FileId(1), vec![3, 7]);
+        let encoded: Vec<u8> = positions.clone().into();
+        assert_eq!(Positions::from_bytes(&encoded).unwrap(), positions);
+
+        let err = |bytes: &[u8]| Positions::from_bytes(bytes).err().map(|err| err.kind());
+        assert_eq!(err(&encoded[..4]), Some(io::ErrorKind::InvalidData));
+        assert_eq!(
+            err(&encoded[..encoded.len() - 1]),
+            Some(io::ErrorKind::InvalidData)
+        );
+        // An offsets count overflowing the entry size.
+        assert_eq!(
+            err(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]),
+            Some(io::ErrorKind::InvalidData)
+        );
+        assert_eq!(Positions::from(encoded[..4].to_vec()), Positions::default());
+    }
+}
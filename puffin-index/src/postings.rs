@@ -0,0 +1,530 @@
+use crate::matchtree::MatchIter;
+use crate::FileId;
+use std::borrow::Cow;
+use std::io;
+use std::ops::Range;
+
+/// Number of postings in a single encoded block.
+const BLOCK_SIZE: usize = 128;
+
+/// Size of a single skip table entry, see layout below.
+const SKIP_SIZE: usize = 8;
+
+/// FileIds is a sorted posting list compressed into blocks of delta and varint
+/// encoded file ids. Every block has an entry in the skip table holding its
+/// last file id, so iterators can jump over blocks without decoding them.
+///
+/// Postings are appended to an unencoded tail which is sealed into a new
+/// block once it fills up. Removing a posting re-encodes only its block, so
+/// blocks may hold fewer postings than BLOCK_SIZE. Blocks can be borrowed from
+/// a memory-mapped shard, they are only copied once the postings change.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct FileIds<'a> {
+    len: usize,
+    /// Last file id and end offset in `blocks` of every sealed block.
+    skips: Vec<(FileId, u32)>,
+    /// Sealed blocks. The first id of every block is encoded as a delta from
+    /// the last id of the previous block.
+    blocks: Cow<'a, [u8]>,
+    /// Postings not sealed into a block yet.
+    tail: Vec<FileId>,
+}
+
+impl<'a> FileIds<'a> {
+    /// Reads the postings encoded in `bytes` without copying the blocks.
+    /// Fails if the skip table doesn't fit the bytes or the blocks.
+    pub fn from_bytes(mut bytes: &'a [u8]) -> io::Result<Self> {
+        if bytes.is_empty() {
+            return Ok(Self::default());
+        }
+        let len = read_varint(&mut bytes) as usize;
+        let block_count = read_varint(&mut bytes) as usize;
+        let (skip_table, blocks) = block_count
+            .checked_mul(SKIP_SIZE)
+            .and_then(|size| Some((bytes.get(..size)?, bytes.get(size..)?)))
+            .ok_or_else(|| invalid_data("truncated posting list skip table"))?;
+        // Every block holds between one and BLOCK_SIZE postings.
+        let min_blocks = len.checked_add(BLOCK_SIZE - 1).map(|len| len / BLOCK_SIZE);
+        if block_count > len || min_blocks.map_or(true, |min| min > block_count) {
+            return Err(invalid_data("posting list length doesn't match its blocks"));
+        }
+        let skips: Vec<(FileId, u32)> = skip_table
+            .chunks(SKIP_SIZE)
+            .map(|entry| {
+                (
+                    FileId(u32::from_le_bytes(entry[0..4].try_into().unwrap())),
+                    u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+                )
+            })
+            .collect();
+        // Blocks follow each other and the last one ends with the bytes.
+        let mut start = 0;
+        for (_, end) in skips.iter() {
+            let end = *end as usize;
+            if end <= start || end > blocks.len() {
+                return Err(invalid_data("posting list block out of bounds"));
+            }
+            start = end;
+        }
+        if start != blocks.len() {
+            return Err(invalid_data("trailing bytes after posting list blocks"));
+        }
+        Ok(Self {
+            len,
+            skips,
+            blocks: Cow::Borrowed(blocks),
+            tail: Vec::new(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, other: FileId) {
+        match self.last() {
+            Some(last) if last == other => return,
+            Some(last) if last > other => {
+                // Slow path, rebuild the whole list to keep it sorted.
+                let mut ids: Vec<FileId> = self.clone().into_iter().collect();
+                if let Err(pos) = ids.binary_search(&other) {
+                    ids.insert(pos, other);
+                }
+                *self = Self::default();
+                ids.into_iter().for_each(|id| self.insert(id));
+                return;
+            }
+            _ => {}
+        }
+
+        self.unseal();
+        self.tail.push(other);
+        self.len += 1;
+        if self.tail.len() == BLOCK_SIZE {
+            self.seal();
+        }
+    }
+
+    /// Adds sorted ids to the list, rebuilding it at most once.
+    pub fn merge(&mut self, ids: Vec<FileId>) {
+        match (self.last(), ids.first()) {
+            (Some(last), Some(first)) if last >= *first => {
+                let mut merged: Vec<FileId> = self.clone().into_iter().collect();
+                merged.extend(ids);
+                merged.sort_unstable();
+                merged.dedup();
+                *self = merged.into_iter().collect();
+            }
+            _ => ids.into_iter().for_each(|id| self.insert(id)),
+        }
+    }
+
+    /// Removes the id, re-encoding only the block holding it.
+    pub fn remove(&mut self, other: &FileId) {
+        let block = self.skips.partition_point(|(last, _)| last < other);
+        if block == self.skips.len() {
+            if let Ok(pos) = self.tail.binary_search(other) {
+                self.tail.remove(pos);
+                self.len -= 1;
+            }
+            return;
+        }
+
+        let mut ids = Vec::with_capacity(BLOCK_SIZE);
+        self.decode_block(block, &mut ids);
+        let Ok(pos) = ids.binary_search(other) else {
+            return;
+        };
+        ids.remove(pos);
+        self.len -= 1;
+        // The next block starts with a delta from the last id of this one.
+        if pos == ids.len() && block + 1 < self.skips.len() {
+            let mut next = Vec::with_capacity(BLOCK_SIZE);
+            self.decode_block(block + 1, &mut next);
+            self.rewrite_blocks(block..block + 2, &[ids, next]);
+        } else {
+            self.rewrite_blocks(block..block + 1, &[ids]);
+        }
+    }
+
+    fn last(&self) -> Option<FileId> {
+        self.tail
+            .last()
+            .or_else(|| self.skips.last().map(|(id, _)| id))
+            .cloned()
+    }
+
+    /// Number of blocks, including the unsealed tail.
+    fn block_count(&self) -> usize {
+        self.skips.len() + usize::from(!self.tail.is_empty())
+    }
+
+    fn block_last(&self, block: usize) -> &FileId {
+        match self.skips.get(block) {
+            Some((id, _)) => id,
+            None => self.tail.last().unwrap(),
+        }
+    }
+
+    fn decode_block(&self, block: usize, into: &mut Vec<FileId>) {
+        into.clear();
+        if block == self.skips.len() {
+            into.extend_from_slice(&self.tail);
+            return;
+        }
+
+        let (mut prev, start) = match block {
+            0 => (0, 0),
+            _ => (self.skips[block - 1].0 .0, self.skips[block - 1].1 as usize),
+        };
+        let end = self.skips[block].1 as usize;
+        let mut bytes = &self.blocks[start..end];
+        while !bytes.is_empty() {
+            prev = prev.wrapping_add(read_varint(&mut bytes) as u32);
+            into.push(FileId(prev));
+        }
+    }
+
+    /// Replaces the sealed blocks in `range` with the encoded `replacement`,
+    /// dropping empty blocks, and shifts the offsets of the following blocks.
+    fn rewrite_blocks(&mut self, range: Range<usize>, replacement: &[Vec<FileId>]) {
+        let (mut prev, start) = match range.start {
+            0 => (0, 0),
+            block => (self.skips[block - 1].0 .0, self.skips[block - 1].1),
+        };
+        let end = self.skips[range.end - 1].1;
+        let mut bytes = Vec::new();
+        let mut skips = Vec::with_capacity(replacement.len());
+        for ids in replacement.iter().filter(|ids| !ids.is_empty()) {
+            for id in ids {
+                write_varint(&mut bytes, u64::from(id.0 - prev));
+                prev = id.0;
+            }
+            skips.push((FileId(prev), start + bytes.len() as u32));
+        }
+        let new_end = start + bytes.len() as u32;
+        self.blocks
+            .to_mut()
+            .splice(start as usize..end as usize, bytes);
+        for (_, offset) in self.skips[range.end..].iter_mut() {
+            *offset = *offset - end + new_end;
+        }
+        self.skips.splice(range, skips);
+    }
+
+    /// Encodes the tail into a new block.
+    fn seal(&mut self) {
+        let Some(last) = self.tail.last().cloned() else {
+            return;
+        };
+        let mut prev = self.skips.last().map_or(0, |(id, _)| id.0);
+        let blocks = self.blocks.to_mut();
+        for id in self.tail.drain(..) {
+            write_varint(blocks, u64::from(id.0 - prev));
+            prev = id.0;
+        }
+        self.skips.push((last, self.blocks.len() as u32));
+    }
+
+    /// Decodes the last block back into the tail if it isn't full, so new
+    /// postings can be appended to it.
+    fn unseal(&mut self) {
+        if !self.tail.is_empty() || self.skips.is_empty() {
+            return;
+        }
+        let block = self.skips.len() - 1;
+        let mut tail = Vec::with_capacity(BLOCK_SIZE);
+        self.decode_block(block, &mut tail);
+        if tail.len() == BLOCK_SIZE {
+            return;
+        }
+        self.skips.pop();
+        self.blocks
+            .to_mut()
+            .truncate(self.skips.last().map_or(0, |(_, end)| *end as usize));
+        self.tail = tail;
+    }
+}
+
+/*
+FileIds layout:
+[postings count][blocks count][skip table][blocks]
+<---varint-----><---varint---><-8 bytes * blocks count->
+
+Skip table entry:
+[last file id][block end offset]
+<---4 byte---><----4 byte------>
+*/
+impl From<Vec<u8>> for FileIds<'static> {
+    /// Treats a corrupted posting list as empty.
+    fn from(value: Vec<u8>) -> Self {
+        let FileIds {
+            len, skips, blocks, ..
+        } = match FileIds::from_bytes(&value) {
+            Ok(ids) => ids,
+            Err(err) => {
+                log::warn!("failed to read posting list: {}", err);
+                return Self::default();
+            }
+        };
+        Self {
+            len,
+            skips,
+            blocks: Cow::Owned(blocks.into_owned()),
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl From<FileIds<'_>> for Vec<u8> {
+    fn from(mut val: FileIds<'_>) -> Self {
+        val.seal();
+        let mut bytes = Vec::with_capacity(10 + val.skips.len() * SKIP_SIZE + val.blocks.len());
+        write_varint(&mut bytes, val.len as u64);
+        write_varint(&mut bytes, val.skips.len() as u64);
+        for (id, end) in val.skips.iter() {
+            bytes.extend(u32::to_le_bytes(id.0));
+            bytes.extend(u32::to_le_bytes(*end));
+        }
+        bytes.extend_from_slice(&val.blocks);
+        bytes
+    }
+}
+
+impl<'a> IntoIterator for FileIds<'a> {
+    type Item = FileId;
+    type IntoIter = FileIdsIter<'a>;
+
+    /// Returns an iterator that decodes the postings lazily, one block at a time.
+    fn into_iter(self) -> FileIdsIter<'a> {
+        FileIdsIter {
+            ids: self,
+            block: 0,
+            decoded: Vec::new(),
+            pos: None,
+        }
+    }
+}
+
+impl FromIterator<FileId> for FileIds<'static> {
+    fn from_iter<T: IntoIterator<Item = FileId>>(iter: T) -> Self {
+        let mut ids = Self::default();
+        iter.into_iter().for_each(|id| ids.insert(id));
+        ids
+    }
+}
+
+/// FileIdsIter walks the encoded posting list, decoding only the blocks it
+/// actually visits.
+pub(crate) struct FileIdsIter<'a> {
+    ids: FileIds<'a>,
+    /// Index of the decoded block.
+    block: usize,
+    decoded: Vec<FileId>,
+    /// Position of the current posting in the decoded block.
+    pos: Option<usize>,
+}
+
+impl FileIdsIter<'_> {
+    fn load(&mut self, block: usize) {
+        self.block = block;
+        if block < self.ids.block_count() {
+            self.ids.decode_block(block, &mut self.decoded);
+        } else {
+            self.decoded.clear();
+        }
+    }
+
+    fn current(&self) -> Option<FileId> {
+        self.decoded.get(self.pos?).cloned()
+    }
+}
+
+impl Iterator for FileIdsIter<'_> {
+    type Item = FileId;
+
+    fn next(&mut self) -> Option<FileId> {
+        MatchIter::next(self)
+    }
+}
+
+impl MatchIter for FileIdsIter<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        let pos = match self.pos {
+            None => {
+                self.load(0);
+                0
+            }
+            Some(pos) if pos + 1 < self.decoded.len() => pos + 1,
+            Some(_) if self.block < self.ids.block_count() => {
+                self.load(self.block + 1);
+                0
+            }
+            Some(pos) => pos,
+        };
+        self.pos = Some(pos);
+        self.current()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        if let Some(current) = self.current() {
+            if current >= *target {
+                return Some(current);
+            }
+        }
+        if self.pos.is_none() {
+            self.load(0);
+        }
+
+        // Use the skip table to find the first block that may contain target.
+        if self.block < self.ids.block_count() && self.ids.block_last(self.block) < target {
+            let skip = self.ids.skips[self.block..].partition_point(|(last, _)| last < target);
+            self.load(self.block + skip);
+        }
+
+        // Everything before the current posting is below target already.
+        let pos = self.decoded.partition_point(|id| id < target);
+        self.pos = Some(pos);
+        self.current()
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        // Bits past the 64th can only come from corrupted data.
+        value |= u64::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return value;
+        }
+        shift = shift.saturating_add(7);
+    }
+    *bytes = &[];
+    value
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::postings::*;
+
+    fn file_ids(ids: impl IntoIterator<Item = u32>) -> FileIds<'static> {
+        ids.into_iter().map(FileId).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let ids = file_ids((0..1000).map(|i| i * 3));
+        assert_eq!(ids.len, 1000);
+
+        let encoded: Vec<u8> = ids.into();
+        // 1000 postings with a delta of 3 fit in a byte each.
+        assert!(encoded.len() < 1000 + 8 * SKIP_SIZE + 4);
+
+        let expected = (0..1000).map(|i| FileId(i * 3)).collect::<Vec<_>>();
+        let borrowed = FileIds::from_bytes(&encoded).unwrap();
+        assert_eq!(borrowed.into_iter().collect::<Vec<_>>(), expected);
+        let decoded = FileIds::from(encoded);
+        assert_eq!(decoded.into_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_insert() {
+        // Appending to a deserialized list with a partial last block.
+        let mut ids = FileIds::from(Vec::<u8>::from(file_ids(0..200)));
+        ids.insert(FileId(199));
+        ids.insert(FileId(300));
+        // Out of order insert.
+        ids.insert(FileId(250));
+        ids.insert(FileId(u32::MAX));
+
+        let mut expected: Vec<FileId> = (0..200).map(FileId).collect();
+        expected.extend([FileId(250), FileId(300), FileId(u32::MAX)]);
+        assert_eq!(ids.len, expected.len());
+        assert_eq!(ids.clone().into_iter().collect::<Vec<_>>(), expected);
+
+        ids.remove(&FileId(250));
+        ids.remove(&FileId(251));
+        expected.retain(|id| *id != FileId(250));
+        assert_eq!(ids.len, expected.len());
+        assert_eq!(ids.into_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_remove() {
+        let encoded: Vec<u8> = file_ids(0..300).into();
+        let mut ids = FileIds::from_bytes(&encoded).unwrap();
+        let mut expected: Vec<FileId> = (0..300).map(FileId).collect();
+        // The middle and the end of the first block, then all of the second.
+        let removed = [FileId(5), FileId(127)]
+            .into_iter()
+            .chain((128..256).map(FileId))
+            .chain([FileId(299), FileId(1000)]);
+        for id in removed {
+            ids.remove(&id);
+            expected.retain(|other| *other != id);
+        }
+        assert_eq!(ids.len, expected.len());
+        assert_eq!(ids.skips.len(), 2);
+        assert_eq!(ids.clone().into_iter().collect::<Vec<_>>(), expected);
+
+        // Partial blocks survive encoding and further inserts.
+        let encoded: Vec<u8> = ids.into();
+        let mut ids = FileIds::from_bytes(&encoded).unwrap();
+        ids.insert(FileId(400));
+        expected.push(FileId(400));
+        assert_eq!(ids.len, expected.len());
+        assert_eq!(ids.into_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_seek() {
+        let ids = FileIds::from(Vec::<u8>::from(file_ids((0..1000).map(|i| i * 2))));
+        let mut iter = ids.into_iter();
+        assert_eq!(iter.seek(&FileId(0)), Some(FileId(0)));
+        assert_eq!(MatchIter::next(&mut iter), Some(FileId(2)));
+        assert_eq!(iter.seek(&FileId(1001)), Some(FileId(1002)));
+        assert_eq!(iter.seek(&FileId(500)), Some(FileId(1002)));
+        assert_eq!(MatchIter::next(&mut iter), Some(FileId(1004)));
+        assert_eq!(iter.seek(&FileId(1998)), Some(FileId(1998)));
+        assert_eq!(iter.seek(&FileId(1999)), None);
+        assert_eq!(MatchIter::next(&mut iter), None);
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let encoded: Vec<u8> = file_ids(0..300).into();
+        let err = |bytes: &[u8]| FileIds::from_bytes(bytes).err().map(|err| err.kind());
+        assert_eq!(err(&encoded), None);
+        // Truncated skip table and blocks.
+        assert_eq!(err(&encoded[..4]), Some(io::ErrorKind::InvalidData));
+        assert_eq!(
+            err(&encoded[..encoded.len() - 1]),
+            Some(io::ErrorKind::InvalidData)
+        );
+        // A block count overflowing the skip table size.
+        assert_eq!(
+            err(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]),
+            Some(io::ErrorKind::InvalidData)
+        );
+        // The first block ending past the blocks.
+        let mut reordered = encoded.clone();
+        reordered[7..11].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(err(&reordered), Some(io::ErrorKind::InvalidData));
+        // Garbage decodes to an empty list instead of panicking.
+        assert!(FileIds::from(vec![0xff; 32]).is_empty());
+    }
+}
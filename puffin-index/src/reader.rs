@@ -0,0 +1,682 @@
+use crate::history::{self, History};
+use crate::lang;
+use crate::matchtree::{And, Filter, MatchIter, Not, Or, Postings};
+use crate::ngram::split_ngrams;
+use crate::options::SearchOptions;
+use crate::positions::{self, Positions};
+use crate::postings::FileIds;
+use crate::regexp::NgramQuery;
+use crate::result::{self, FileMatch, LineMatch, SearchResult, SymbolMatch};
+use crate::score;
+use crate::{search, FileId};
+use puffin_query::{DocumentType, QueryNode};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::time::Instant;
+
+/// N-gram tables of an index.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum Grams {
+    Content,
+    Path,
+    /// N-grams of the names of definitions.
+    Symbol,
+}
+
+/// IndexReader gives queries access to the index data, no matter whether it
+/// lives in the SSTables of an `Index` or in a memory-mapped shard.
+pub(crate) trait IndexReader {
+    /// Returns the posting list of the n-gram, empty if it isn't indexed.
+    fn postings(&self, grams: Grams, ngram: &str) -> FileIds<'_>;
+
+    /// Whether positions of the content trigrams were recorded.
+    fn has_positions(&self) -> bool;
+
+    fn positions(&self, trigram: &str) -> Positions;
+
+    fn file(&self, file_id: &FileId) -> Option<Cow<'_, search::File>>;
+
+    /// Same as `file(file_id).filename`, without loading the content.
+    fn filename(&self, file_id: &FileId) -> Option<&str>;
+
+    /// Returns ids of all indexed files, in ascending order.
+    fn file_ids(&self) -> Box<dyn Iterator<Item = FileId> + '_>;
+
+    /// Returns ids of all files of the language, `name` being its canonical name.
+    fn language(&self, name: &str) -> BTreeSet<FileId>;
+
+    /// Returns ids of the files of every repository, by repository name.
+    fn repositories(&self) -> &BTreeMap<String, BTreeSet<FileId>>;
+
+    /// Returns ids of the files of every branch, by branch name.
+    fn branches(&self) -> &BTreeMap<String, BTreeSet<FileId>>;
+
+    /// Returns the documents of the commit history.
+    fn history(&self) -> &History;
+}
+
+pub(crate) fn search(
+    reader: &dyn IndexReader,
+    query: QueryNode,
+    options: &SearchOptions,
+) -> SearchResult {
+    let started = Instant::now();
+    let mut patterns = Vec::new();
+    let mut symbol_patterns = Vec::new();
+    let sensitive = query.is_case_sensitive();
+    content_patterns(&query, sensitive, &mut patterns);
+    collect_symbol_patterns(&query, sensitive, &mut symbol_patterns);
+    let query = if searches_history(&query) {
+        query
+    } else {
+        QueryNode::And {
+            lhs: Box::new(query),
+            rhs: Box::new(QueryNode::Type(DocumentType::File)),
+        }
+    };
+
+    // Files are only loaded while evaluating the query and scoring them, the
+    // results keep nothing but their matches.
+    let mut result = SearchResult::default();
+    let mut match_count = 0;
+    let mut iter = match_iter(reader, query, sensitive);
+    while let Some(fid) = iter.next() {
+        let Some(f) = reader.file(&fid) else {
+            continue;
+        };
+        let symbols = symbol_matches(&f, &symbol_patterns);
+        let mut line_matches = result::line_matches(&f.content, &patterns, &symbols);
+        let score = score::score(&f.filename, &f.content, &line_matches, &patterns);
+        if let Some(max) = options.max_line_matches {
+            result.truncated |= line_matches.len() > max;
+            line_matches.truncate(max);
+        }
+        if options.context_lines > 0 {
+            result::add_context(&f.content, &mut line_matches, options.context_lines);
+        }
+        // Files matched by their name or language alone count as one match.
+        match_count += line_matches
+            .iter()
+            .map(|l| l.matches.len())
+            .sum::<usize>()
+            .max(1);
+        let repository = f.repository.as_ref();
+        result.files.push(FileMatch {
+            score,
+            url: repository.and_then(|r| file_url(r, &f.filename, &line_matches)),
+            repository: repository.map(|r| r.name.clone()).unwrap_or_default(),
+            branches: f.branches.clone(),
+            line_matches,
+            symbols,
+            filename: f.filename.clone(),
+            file_type: f.file_type.clone(),
+            kind: f.kind(),
+            commit: f.commit.clone(),
+        });
+        // Any file left may score higher than the ones kept, so the limit on
+        // files only bounds the memory held by the results.
+        if let Some(max) = options.max_files {
+            // Keeping twice as many files as needed makes pruning amortized.
+            if result.files.len() >= 2 * max.max(1) {
+                sort_by_score(&mut result.files);
+                result.files.truncate(max);
+                result.truncated = true;
+            }
+        }
+
+        // Past the limits there is no telling how the remaining files would
+        // rank, so the files found so far are the results.
+        let exhausted = options.max_matches.is_some_and(|max| match_count >= max)
+            || options
+                .time_budget
+                .is_some_and(|budget| started.elapsed() >= budget);
+        if exhausted {
+            result.truncated |= iter.next().is_some();
+            break;
+        }
+    }
+
+    sort_by_score(&mut result.files);
+    if let Some(max) = options.max_files {
+        result.truncated |= result.files.len() > max;
+        result.files.truncate(max);
+    }
+    result
+}
+
+/// Whether the query may match documents of the commit history, which only
+/// queries with one of the history atoms do.
+fn searches_history(query: &QueryNode) -> bool {
+    match query {
+        QueryNode::Or { lhs, rhs } | QueryNode::And { lhs, rhs } => {
+            searches_history(lhs) || searches_history(rhs)
+        }
+        QueryNode::Not(query) | QueryNode::Case { query, .. } => searches_history(query),
+        QueryNode::Type(_)
+        | QueryNode::Author(_)
+        | QueryNode::AuthorRegex(_)
+        | QueryNode::Before(_)
+        | QueryNode::After(_) => true,
+        QueryNode::Lang(_)
+        | QueryNode::File(_)
+        | QueryNode::FileRegex(_)
+        | QueryNode::Repo(_)
+        | QueryNode::RepoRegex(_)
+        | QueryNode::Branch(_)
+        | QueryNode::BranchRegex(_)
+        | QueryNode::Symbol(_)
+        | QueryNode::SymbolRegex(_)
+        | QueryNode::Term(_)
+        | QueryNode::Regex(_) => false,
+    }
+}
+
+/// Expands the URL template of the repository for the file, linking to the
+/// first matching line.
+fn file_url(
+    repository: &search::Repository,
+    filename: &str,
+    line_matches: &[LineMatch],
+) -> Option<String> {
+    if repository.url_template.is_empty() {
+        return None;
+    }
+    let path = Path::new(filename)
+        .strip_prefix(&repository.root)
+        .ok()?
+        .to_str()?;
+    let line = line_matches.first().map_or(1, |l| l.line_number);
+    Some(
+        repository
+            .url_template
+            .replace("{path}", path)
+            .replace("{line}", &line.to_string()),
+    )
+}
+
+/// Merges the results of several shards into the best files of all of them,
+/// within the limits of the options.
+pub(crate) fn merge(results: Vec<SearchResult>, options: &SearchOptions) -> SearchResult {
+    let mut merged = SearchResult::default();
+    for result in results {
+        merged.truncated |= result.truncated;
+        merged.files.extend(result.files);
+    }
+    sort_by_score(&mut merged.files);
+    if let Some(max) = options.max_files {
+        merged.truncated |= merged.files.len() > max;
+        merged.files.truncate(max);
+    }
+    merged
+}
+
+/// Sorts the matches by descending score. Ties are broken by filename, so
+/// the order doesn't depend on file ids.
+fn sort_by_score(matches: &mut [FileMatch]) {
+    matches.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.filename.cmp(&b.filename))
+    });
+}
+
+/// Returns paths of all indexed files that contain the given fragment.
+pub(crate) fn find_files(reader: &dyn IndexReader, fragment: &str) -> Vec<String> {
+    let mut files = PathGrams::new(fragment, reader, true);
+    let mut result = Vec::new();
+    while let Some(fid) = files.next() {
+        if reader.history().contains(&fid) {
+            continue;
+        }
+        if let Some(filename) = reader.filename(&fid) {
+            result.push(filename.to_string());
+        }
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+/// Builds match tree for the trigram query plan. Posting lists are decoded
+/// lazily as the tree gets evaluated.
+fn ngram_iter<'a>(
+    reader: &'a dyn IndexReader,
+    grams: Grams,
+    query: &NgramQuery,
+) -> Box<dyn MatchIter + 'a> {
+    match query {
+        NgramQuery::All => Box::new(Postings::new(reader.file_ids())),
+        NgramQuery::None => Box::new(Postings::new([])),
+        NgramQuery::Ngram(ngram) => Box::new(
+            reader
+                .postings(grams, ngram.to_string().as_str())
+                .into_iter(),
+        ),
+        NgramQuery::And(subs) => Box::new(And::new(
+            subs.iter()
+                .map(|sub| ngram_iter(reader, grams, sub))
+                .collect(),
+        )),
+        NgramQuery::Or(subs) => Box::new(Or::new(
+            subs.iter()
+                .map(|sub| ngram_iter(reader, grams, sub))
+                .collect(),
+        )),
+    }
+}
+
+/// Builds match tree for the query, `sensitive` being the case sensitivity
+/// of its atoms.
+fn match_iter<'a>(
+    reader: &'a dyn IndexReader,
+    query: QueryNode,
+    sensitive: bool,
+) -> Box<dyn MatchIter + 'a> {
+    match query {
+        QueryNode::Or { lhs, rhs } => Box::new(Or::new(vec![
+            match_iter(reader, *lhs, sensitive),
+            match_iter(reader, *rhs, sensitive),
+        ])),
+        QueryNode::And { lhs, rhs } => Box::new(And::new(vec![
+            match_iter(reader, *lhs, sensitive),
+            match_iter(reader, *rhs, sensitive),
+        ])),
+        QueryNode::Not(q) => Box::new(Not::new(
+            Box::new(Postings::new(reader.file_ids())),
+            match_iter(reader, *q, sensitive),
+        )),
+        QueryNode::Case { sensitive, query } => match_iter(reader, *query, sensitive),
+        QueryNode::Lang(l) => Box::new(Languages::new(l, reader)),
+        QueryNode::Repo(r) => Box::new(NamedFiles::new(reader.repositories(), &r, sensitive)),
+        QueryNode::RepoRegex(r) => {
+            Box::new(NamedFiles::regex(reader.repositories(), &r, sensitive))
+        }
+        QueryNode::Branch(b) => Box::new(NamedFiles::new(reader.branches(), &b, sensitive)),
+        QueryNode::BranchRegex(r) => Box::new(NamedFiles::regex(reader.branches(), &r, sensitive)),
+        QueryNode::Type(t) => Box::new(documents(reader, t)),
+        QueryNode::Author(a) => {
+            Box::new(NamedFiles::new(reader.history().authors(), &a, sensitive))
+        }
+        QueryNode::AuthorRegex(r) => {
+            Box::new(NamedFiles::regex(reader.history().authors(), &r, sensitive))
+        }
+        QueryNode::Before(d) => Box::new(dated(reader, &d, |day| ..day)),
+        QueryNode::After(d) => Box::new(dated(reader, &d, |day| day..)),
+        QueryNode::File(f) => Box::new(PathGrams::new(&f, reader, sensitive)),
+        QueryNode::FileRegex(r) => Box::new(PathGrams::regex(&r, reader, sensitive)),
+        QueryNode::Symbol(s) => Box::new(SymbolGrams::new(&s, reader, sensitive)),
+        QueryNode::SymbolRegex(r) => Box::new(SymbolGrams::regex(&r, reader, sensitive)),
+        QueryNode::Term(t) => Box::new(ContentGrams::new(t, reader, sensitive)),
+        QueryNode::Regex(r) => Box::new(RegexGrams::new(r, reader, sensitive)),
+    }
+}
+
+/// Builds the regex used to verify content matches of a regex query.
+fn content_regex(q: &str, sensitive: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(q)
+        .multi_line(true)
+        .case_insensitive(!sensitive)
+        .build()
+}
+
+/// Builds the regex matching the literal, ignoring case unless `sensitive`.
+fn literal_regex(q: &str, sensitive: bool) -> Regex {
+    RegexBuilder::new(&regex::escape(q))
+        .case_insensitive(!sensitive)
+        .build()
+        .unwrap()
+}
+
+/// Collects patterns of all content atoms that have to be present in the
+/// matching documents. Atoms under `NOT` are skipped as they never produce
+/// matches.
+fn content_patterns(query: &QueryNode, sensitive: bool, patterns: &mut Vec<Regex>) {
+    match query {
+        QueryNode::Or { lhs, rhs } | QueryNode::And { lhs, rhs } => {
+            content_patterns(lhs, sensitive, patterns);
+            content_patterns(rhs, sensitive, patterns);
+        }
+        QueryNode::Case { sensitive, query } => content_patterns(query, *sensitive, patterns),
+        QueryNode::Term(t) => patterns.push(literal_regex(t, sensitive)),
+        QueryNode::Regex(r) => patterns.extend(content_regex(r, sensitive).ok()),
+        QueryNode::Not(_)
+        | QueryNode::Lang(_)
+        | QueryNode::File(_)
+        | QueryNode::FileRegex(_)
+        | QueryNode::Repo(_)
+        | QueryNode::RepoRegex(_)
+        | QueryNode::Branch(_)
+        | QueryNode::BranchRegex(_)
+        | QueryNode::Symbol(_)
+        | QueryNode::SymbolRegex(_)
+        | QueryNode::Type(_)
+        | QueryNode::Author(_)
+        | QueryNode::AuthorRegex(_)
+        | QueryNode::Before(_)
+        | QueryNode::After(_) => {}
+    }
+}
+
+/// Collects patterns of all `sym:` atoms that have to be present in the
+/// matching documents, skipping the ones under `NOT`.
+fn collect_symbol_patterns(query: &QueryNode, sensitive: bool, patterns: &mut Vec<Regex>) {
+    match query {
+        QueryNode::Or { lhs, rhs } | QueryNode::And { lhs, rhs } => {
+            collect_symbol_patterns(lhs, sensitive, patterns);
+            collect_symbol_patterns(rhs, sensitive, patterns);
+        }
+        QueryNode::Case { sensitive, query } => {
+            collect_symbol_patterns(query, *sensitive, patterns)
+        }
+        QueryNode::Symbol(s) => patterns.push(literal_regex(s, sensitive)),
+        QueryNode::SymbolRegex(r) => patterns.extend(name_regex(r, sensitive).ok()),
+        QueryNode::Not(_)
+        | QueryNode::Lang(_)
+        | QueryNode::File(_)
+        | QueryNode::FileRegex(_)
+        | QueryNode::Repo(_)
+        | QueryNode::RepoRegex(_)
+        | QueryNode::Branch(_)
+        | QueryNode::BranchRegex(_)
+        | QueryNode::Type(_)
+        | QueryNode::Author(_)
+        | QueryNode::AuthorRegex(_)
+        | QueryNode::Before(_)
+        | QueryNode::After(_)
+        | QueryNode::Term(_)
+        | QueryNode::Regex(_) => {}
+    }
+}
+
+/// Builds the regex matching names, such as the ones of symbols, for a regex
+/// query.
+fn name_regex(q: &str, sensitive: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(q).case_insensitive(!sensitive).build()
+}
+
+/// Returns the symbols of the file matching any of the patterns.
+fn symbol_matches(file: &search::File, patterns: &[Regex]) -> Vec<SymbolMatch> {
+    file.symbols
+        .iter()
+        .filter(|s| patterns.iter().any(|re| re.is_match(&s.name)))
+        .map(|s| SymbolMatch {
+            name: s.name.clone(),
+            kind: s.kind(),
+            line_number: s.line_number as usize,
+            byte_range: s.start as usize..s.start as usize + s.name.len(),
+        })
+        .collect()
+}
+
+struct ContentGrams<'a>(Filter<'a>);
+
+impl<'a> ContentGrams<'a> {
+    pub fn new(q: String, index: &'a dyn IndexReader, sensitive: bool) -> Self {
+        // Having all the trigrams doesn't mean they are next to each other,
+        // every candidate needs to be verified either through the trigram
+        // positions or against its content. Trigrams are case folded, so
+        // positions only verify case-insensitive queries.
+        let positions = Self::positions(&q, index);
+        let candidates = ngram_iter(index, Grams::Content, &NgramQuery::from_literal(&q));
+        let re = (!sensitive).then(|| literal_regex(&q, sensitive));
+        let verify = move |fid: &FileId| {
+            index.file(fid).is_some_and(|f| match &re {
+                Some(re) => re.is_match(&f.content),
+                None => f.content.contains(&q),
+            })
+        };
+
+        Self(Filter::new(candidates, move |fid| match &positions {
+            Some(positions) => {
+                positions::contains_phrase(positions, fid) && (!sensitive || verify(fid))
+            }
+            None => verify(fid),
+        }))
+    }
+
+    /// Returns positions of the query trigrams, in order, if the query can be
+    /// verified using the positional index.
+    fn positions(q: &str, index: &dyn IndexReader) -> Option<Vec<Positions>> {
+        if !index.has_positions() || q.contains(['\n', '\r']) {
+            return None;
+        }
+        let trigrams = split_ngrams(q);
+        if trigrams.is_empty() {
+            return None;
+        }
+        Some(
+            trigrams
+                .into_iter()
+                .map(|(trigram, _)| index.positions(&trigram.to_string()))
+                .collect(),
+        )
+    }
+}
+
+impl MatchIter for ContentGrams<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        self.0.next()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        self.0.seek(target)
+    }
+}
+
+struct Languages(Postings);
+
+impl Languages {
+    pub fn new(q: String, index: &dyn IndexReader) -> Self {
+        let Some(language) = lang::find(&q) else {
+            log::warn!("unknown language {:?}", q);
+            return Self(Postings::new([]));
+        };
+
+        Self(Postings::new(index.language(language.name)))
+    }
+}
+
+impl MatchIter for Languages {
+    fn next(&mut self) -> Option<FileId> {
+        self.0.next()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        self.0.seek(target)
+    }
+}
+
+/// Logs the invalid regex and returns an atom matching nothing.
+fn invalid_regex<'a>(q: &str, err: &dyn std::fmt::Display) -> Filter<'a> {
+    log::warn!("invalid regex {:?}: {}", q, err);
+    Filter::new(Box::new(Postings::new([])), |_| false)
+}
+
+/// Documents of the type. Files are the documents that aren't part of the
+/// history.
+fn documents(reader: &dyn IndexReader, t: DocumentType) -> Postings {
+    let history = reader.history();
+    match t {
+        DocumentType::File => Postings::new(reader.file_ids().filter(|id| !history.contains(id))),
+        DocumentType::Commit => Postings::new(history.commits().iter().cloned()),
+        DocumentType::Diff => Postings::new(history.diffs().iter().cloned()),
+    }
+}
+
+/// History documents authored within the range of times built from the time
+/// the day of the `YYYY-MM-DD` date starts at.
+fn dated<R: RangeBounds<i64>>(
+    reader: &dyn IndexReader,
+    date: &str,
+    range: impl Fn(i64) -> R,
+) -> Postings {
+    match history::parse_date(date) {
+        Some(day) => Postings::new(reader.history().dated(range(day))),
+        None => {
+            log::warn!("invalid date {:?}", date);
+            Postings::new([])
+        }
+    }
+}
+
+/// Files of the named sets, such as repositories or branches, whose name
+/// matches.
+struct NamedFiles(Postings);
+
+impl NamedFiles {
+    pub fn new(sets: &BTreeMap<String, BTreeSet<FileId>>, q: &str, sensitive: bool) -> Self {
+        let re = literal_regex(q, sensitive);
+        Self::filter(sets, |name| re.is_match(name))
+    }
+
+    pub fn regex(sets: &BTreeMap<String, BTreeSet<FileId>>, q: &str, sensitive: bool) -> Self {
+        match name_regex(q, sensitive) {
+            Ok(re) => Self::filter(sets, |name| re.is_match(name)),
+            Err(err) => {
+                log::warn!("invalid regex {:?}: {}", q, err);
+                Self(Postings::new([]))
+            }
+        }
+    }
+
+    fn filter(sets: &BTreeMap<String, BTreeSet<FileId>>, matches: impl Fn(&str) -> bool) -> Self {
+        let file_ids: BTreeSet<FileId> = sets
+            .iter()
+            .filter(|(name, _)| matches(name))
+            .flat_map(|(_, file_ids)| file_ids.iter().cloned())
+            .collect();
+
+        Self(Postings::new(file_ids))
+    }
+}
+
+impl MatchIter for NamedFiles {
+    fn next(&mut self) -> Option<FileId> {
+        self.0.next()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        self.0.seek(target)
+    }
+}
+
+struct RegexGrams<'a>(Filter<'a>);
+
+impl<'a> RegexGrams<'a> {
+    pub fn new(q: String, index: &'a dyn IndexReader, sensitive: bool) -> Self {
+        let (query, re) = match (NgramQuery::from_regex(&q), content_regex(&q, sensitive)) {
+            (Ok(query), Ok(re)) => (query, re),
+            (Err(err), _) => return Self(invalid_regex(&q, &err)),
+            (_, Err(err)) => return Self(invalid_regex(&q, &err)),
+        };
+
+        let candidates = ngram_iter(index, Grams::Content, &query);
+        Self(Filter::new(candidates, move |fid| {
+            index.file(fid).is_some_and(|f| re.is_match(&f.content))
+        }))
+    }
+}
+
+impl MatchIter for RegexGrams<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        self.0.next()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        self.0.seek(target)
+    }
+}
+
+struct PathGrams<'a>(Filter<'a>);
+
+impl<'a> PathGrams<'a> {
+    pub fn new(q: &str, index: &'a dyn IndexReader, sensitive: bool) -> Self {
+        let query = NgramQuery::from_literal(q);
+        if sensitive {
+            let q = q.to_string();
+            return Self::filter(query, index, move |filename| filename.contains(&q));
+        }
+        let re = literal_regex(q, sensitive);
+        Self::filter(query, index, move |filename| re.is_match(filename))
+    }
+
+    pub fn regex(q: &str, index: &'a dyn IndexReader, sensitive: bool) -> Self {
+        let re = RegexBuilder::new(q).case_insensitive(!sensitive).build();
+        let (query, re) = match (NgramQuery::from_regex(q), re) {
+            (Ok(query), Ok(re)) => (query, re),
+            (Err(err), _) => return Self(invalid_regex(q, &err)),
+            (_, Err(err)) => return Self(invalid_regex(q, &err)),
+        };
+
+        Self::filter(query, index, move |filename| re.is_match(filename))
+    }
+
+    fn filter(
+        query: NgramQuery,
+        index: &'a dyn IndexReader,
+        verify: impl Fn(&str) -> bool + 'a,
+    ) -> Self {
+        let candidates = ngram_iter(index, Grams::Path, &query);
+        Self(Filter::new(candidates, move |fid| {
+            index.filename(fid).is_some_and(&verify)
+        }))
+    }
+}
+
+impl MatchIter for PathGrams<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        self.0.next()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        self.0.seek(target)
+    }
+}
+
+/// Files defining a symbol whose name matches the query.
+struct SymbolGrams<'a>(Filter<'a>);
+
+impl<'a> SymbolGrams<'a> {
+    pub fn new(q: &str, index: &'a dyn IndexReader, sensitive: bool) -> Self {
+        let re = literal_regex(q, sensitive);
+        Self::filter(NgramQuery::from_literal(q), index, move |name| {
+            re.is_match(name)
+        })
+    }
+
+    pub fn regex(q: &str, index: &'a dyn IndexReader, sensitive: bool) -> Self {
+        let (query, re) = match (NgramQuery::from_regex(q), name_regex(q, sensitive)) {
+            (Ok(query), Ok(re)) => (query, re),
+            (Err(err), _) => return Self(invalid_regex(q, &err)),
+            (_, Err(err)) => return Self(invalid_regex(q, &err)),
+        };
+
+        Self::filter(query, index, move |name| re.is_match(name))
+    }
+
+    fn filter(
+        query: NgramQuery,
+        index: &'a dyn IndexReader,
+        verify: impl Fn(&str) -> bool + 'a,
+    ) -> Self {
+        let candidates = ngram_iter(index, Grams::Symbol, &query);
+        Self(Filter::new(candidates, move |fid| {
+            index
+                .file(fid)
+                .is_some_and(|f| f.symbols.iter().any(|s| verify(&s.name)))
+        }))
+    }
+}
+
+impl MatchIter for SymbolGrams<'_> {
+    fn next(&mut self) -> Option<FileId> {
+        self.0.next()
+    }
+
+    fn seek(&mut self, target: &FileId) -> Option<FileId> {
+        self.0.seek(target)
+    }
+}
@@ -0,0 +1,480 @@
+use crate::ngram::{split_ngrams, Ngram};
+use regex_syntax::hir::{Class, Hir, HirKind};
+use std::collections::BTreeSet;
+
+/// Maximum number of strings kept in an exact set before it gets reduced to
+/// prefixes and suffixes.
+const MAX_EXACT: usize = 7;
+
+/// Maximum number of strings kept in a prefix or suffix set.
+const MAX_SET: usize = 20;
+
+/// Character classes with more characters than this are treated as any char.
+const MAX_CLASS: usize = 16;
+
+/// NgramQuery is a boolean formula over trigrams that every document matching
+/// a regular expression has to satisfy. It only ever over-approximates the set
+/// of matching documents, so candidates still need to be verified against
+/// the regular expression itself.
+// Based on https://swtch.com/~rsc/regexp/regexp4.html and
+// https://github.com/google/codesearch/blob/master/index/regexp.go.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NgramQuery {
+    /// Matches every document.
+    All,
+    /// Matches no document.
+    None,
+    Ngram(Ngram),
+    And(Vec<NgramQuery>),
+    Or(Vec<NgramQuery>),
+}
+
+impl NgramQuery {
+    /// Builds the trigram query for the given regular expression.
+    pub fn from_regex(pattern: &str) -> Result<Self, Box<regex_syntax::Error>> {
+        let hir = regex_syntax::ParserBuilder::new()
+            .multi_line(true)
+            .build()
+            .parse(pattern)
+            .map_err(Box::new)?;
+        let mut info = analyze(&hir);
+        info.add_exact();
+        let query = info.query.and(info.prefix.trigrams());
+        Ok(query.and(info.suffix.trigrams()))
+    }
+
+    /// Builds the trigram query for documents containing the given string.
+    pub fn from_literal(s: &str) -> Self {
+        StringSet::single(s).trigrams()
+    }
+
+    pub fn and(self, other: NgramQuery) -> NgramQuery {
+        NgramQuery::And(vec![self, other]).simplify()
+    }
+
+    pub fn or(self, other: NgramQuery) -> NgramQuery {
+        NgramQuery::Or(vec![self, other]).simplify()
+    }
+
+    fn simplify(self) -> NgramQuery {
+        match self {
+            NgramQuery::And(subs) => {
+                let mut flat = Vec::new();
+                for sub in subs.into_iter().map(NgramQuery::simplify) {
+                    match sub {
+                        NgramQuery::All => {}
+                        NgramQuery::None => return NgramQuery::None,
+                        NgramQuery::And(inner) => flat.extend(inner),
+                        sub => flat.push(sub),
+                    }
+                }
+                dedup(&mut flat);
+                match flat.len() {
+                    0 => NgramQuery::All,
+                    1 => flat.pop().unwrap(),
+                    _ => NgramQuery::And(flat),
+                }
+            }
+            NgramQuery::Or(subs) => {
+                let mut flat = Vec::new();
+                for sub in subs.into_iter().map(NgramQuery::simplify) {
+                    match sub {
+                        NgramQuery::All => return NgramQuery::All,
+                        NgramQuery::None => {}
+                        NgramQuery::Or(inner) => flat.extend(inner),
+                        sub => flat.push(sub),
+                    }
+                }
+                dedup(&mut flat);
+                absorb(&mut flat);
+                match flat.len() {
+                    0 => NgramQuery::None,
+                    1 => flat.pop().unwrap(),
+                    _ => NgramQuery::Or(flat),
+                }
+            }
+            q => q,
+        }
+    }
+}
+
+fn dedup(queries: &mut Vec<NgramQuery>) {
+    let mut unique: Vec<NgramQuery> = Vec::with_capacity(queries.len());
+    for q in queries.drain(..) {
+        if !unique.contains(&q) {
+            unique.push(q);
+        }
+    }
+    *queries = unique;
+}
+
+/// Drops alternatives that require a strict superset of what another
+/// alternative requires, e.g. `(a AND b) OR a` is just `a`.
+fn absorb(queries: &mut Vec<NgramQuery>) {
+    fn atoms(q: &NgramQuery) -> Vec<&NgramQuery> {
+        match q {
+            NgramQuery::And(subs) => subs.iter().collect(),
+            q => vec![q],
+        }
+    }
+
+    let absorbed: Vec<bool> = queries
+        .iter()
+        .map(|q| {
+            let q_atoms = atoms(q);
+            queries.iter().any(|other| {
+                let other_atoms = atoms(other);
+                other_atoms.len() < q_atoms.len() && other_atoms.iter().all(|a| q_atoms.contains(a))
+            })
+        })
+        .collect();
+    let mut absorbed = absorbed.into_iter();
+    queries.retain(|_| !absorbed.next().unwrap());
+}
+
+/// Set of strings used to track exact matches, prefixes and suffixes.
+#[derive(Clone, Default, Debug)]
+struct StringSet(BTreeSet<String>);
+
+impl StringSet {
+    fn single(s: impl Into<String>) -> Self {
+        Self(BTreeSet::from([s.into()]))
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn union(&self, other: &StringSet) -> StringSet {
+        Self(self.0.union(&other.0).cloned().collect())
+    }
+
+    fn cross(&self, other: &StringSet) -> StringSet {
+        let mut result = BTreeSet::new();
+        for a in self.0.iter() {
+            for b in other.0.iter() {
+                result.insert(format!("{}{}", a, b));
+            }
+        }
+        Self(result)
+    }
+
+    /// Keeps at most `n` leading chars of every string.
+    fn truncate_prefix(&self, n: usize) -> StringSet {
+        Self(self.0.iter().map(|s| s.chars().take(n).collect()).collect())
+    }
+
+    /// Keeps at most `n` trailing chars of every string.
+    fn truncate_suffix(&self, n: usize) -> StringSet {
+        Self(
+            self.0
+                .iter()
+                .map(|s| {
+                    let skip = s.chars().count().saturating_sub(n);
+                    s.chars().skip(skip).collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns query matching documents that contain all trigrams of at least
+    /// one of the strings in the set.
+    fn trigrams(&self) -> NgramQuery {
+        if self.0.is_empty() {
+            return NgramQuery::None;
+        }
+        NgramQuery::Or(
+            self.0
+                .iter()
+                .map(|s| {
+                    NgramQuery::And(
+                        split_ngrams(s)
+                            .into_iter()
+                            .map(|(ngram, _)| ngram)
+                            // Content is indexed line by line.
+                            .filter(|ngram| !ngram.to_runes().contains(&'\n'))
+                            .map(NgramQuery::Ngram)
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+        .simplify()
+    }
+}
+
+/// Information about a (sub)expression collected during the analysis.
+struct RegexInfo {
+    /// Whether the expression can match an empty string.
+    can_empty: bool,
+    /// The exact set of strings matched by the expression, if known.
+    exact: Option<StringSet>,
+    /// Possible prefixes of the matched strings; only used when exact is unknown.
+    prefix: StringSet,
+    /// Possible suffixes of the matched strings; only used when exact is unknown.
+    suffix: StringSet,
+    /// Query that has to be satisfied by any document matching the expression.
+    query: NgramQuery,
+}
+
+impl RegexInfo {
+    fn exact(set: StringSet) -> Self {
+        Self {
+            can_empty: set.0.contains(""),
+            exact: Some(set),
+            prefix: StringSet::default(),
+            suffix: StringSet::default(),
+            query: NgramQuery::All,
+        }
+    }
+
+    fn empty_string() -> Self {
+        Self::exact(StringSet::single(""))
+    }
+
+    fn any_char() -> Self {
+        Self {
+            can_empty: false,
+            exact: None,
+            prefix: StringSet::single(""),
+            suffix: StringSet::single(""),
+            query: NgramQuery::All,
+        }
+    }
+
+    fn any_match() -> Self {
+        Self {
+            can_empty: true,
+            ..Self::any_char()
+        }
+    }
+
+    fn no_match() -> Self {
+        Self {
+            can_empty: false,
+            exact: None,
+            prefix: StringSet::default(),
+            suffix: StringSet::default(),
+            query: NgramQuery::None,
+        }
+    }
+
+    /// Possible prefixes of the matched strings.
+    fn prefixes(&self) -> &StringSet {
+        self.exact.as_ref().unwrap_or(&self.prefix)
+    }
+
+    /// Possible suffixes of the matched strings.
+    fn suffixes(&self) -> &StringSet {
+        self.exact.as_ref().unwrap_or(&self.suffix)
+    }
+
+    /// Moves the exact set into the query and prefix/suffix sets.
+    fn add_exact(&mut self) {
+        if let Some(exact) = self.exact.take() {
+            self.query = self.query.clone().and(exact.trigrams());
+            self.prefix = exact.truncate_prefix(2);
+            self.suffix = exact.truncate_suffix(2);
+        }
+    }
+
+    /// Keeps the sets small enough for the cross products to stay cheap.
+    fn simplify(mut self) -> Self {
+        if self.exact.as_ref().is_some_and(|e| e.len() > MAX_EXACT) {
+            self.add_exact();
+        }
+        if self.exact.is_none() {
+            self.prefix = simplify_set(&mut self.query, self.prefix, StringSet::truncate_prefix);
+            self.suffix = simplify_set(&mut self.query, self.suffix, StringSet::truncate_suffix);
+        }
+        self
+    }
+}
+
+fn simplify_set(
+    query: &mut NgramQuery,
+    set: StringSet,
+    truncate: fn(&StringSet, usize) -> StringSet,
+) -> StringSet {
+    if set.len() <= MAX_SET {
+        return set;
+    }
+    // Remember what the long strings required before shortening them.
+    *query = query.clone().and(set.trigrams());
+    let set = truncate(&set, 2);
+    if set.len() <= MAX_SET {
+        set
+    } else {
+        StringSet::single("")
+    }
+}
+
+fn analyze(hir: &Hir) -> RegexInfo {
+    let info = match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => RegexInfo::empty_string(),
+        HirKind::Literal(lit) => {
+            RegexInfo::exact(StringSet::single(String::from_utf8_lossy(&lit.0)))
+        }
+        HirKind::Class(Class::Unicode(class)) => {
+            let chars = class.ranges().iter().map(|r| r.len()).sum::<usize>();
+            if chars == 0 {
+                RegexInfo::no_match()
+            } else if chars > MAX_CLASS {
+                RegexInfo::any_char()
+            } else {
+                RegexInfo::exact(StringSet(
+                    class
+                        .iter()
+                        .flat_map(|r| r.start()..=r.end())
+                        .map(String::from)
+                        .collect(),
+                ))
+            }
+        }
+        HirKind::Class(Class::Bytes(_)) => RegexInfo::any_char(),
+        HirKind::Capture(capture) => analyze(&capture.sub),
+        HirKind::Repetition(rep) => {
+            let sub = analyze(&rep.sub);
+            match (rep.min, rep.max) {
+                (0, Some(1)) => alternate(sub, RegexInfo::empty_string()),
+                (0, _) => RegexInfo::any_match(),
+                _ => plus(sub),
+            }
+        }
+        HirKind::Concat(subs) => subs
+            .iter()
+            .map(analyze)
+            .reduce(concat)
+            .unwrap_or_else(RegexInfo::empty_string),
+        HirKind::Alternation(subs) => subs
+            .iter()
+            .map(analyze)
+            .reduce(alternate)
+            .unwrap_or_else(RegexInfo::no_match),
+    };
+    info.simplify()
+}
+
+fn concat(x: RegexInfo, y: RegexInfo) -> RegexInfo {
+    let mut query = x.query.clone().and(y.query.clone());
+
+    if let (Some(x_exact), Some(y_exact)) = (&x.exact, &y.exact) {
+        let exact = x_exact.cross(y_exact);
+        return RegexInfo {
+            can_empty: x.can_empty && y.can_empty,
+            query,
+            ..RegexInfo::exact(exact)
+        };
+    }
+
+    let mut prefix = match &x.exact {
+        Some(exact) => exact.cross(y.prefixes()),
+        None => x.prefix.clone(),
+    };
+    if x.can_empty {
+        prefix = prefix.union(y.prefixes());
+    }
+
+    let mut suffix = match &y.exact {
+        Some(exact) => x.suffixes().cross(exact),
+        None => y.suffix.clone(),
+    };
+    if y.can_empty {
+        suffix = suffix.union(x.suffixes());
+    }
+
+    // The end of x and the start of y are adjacent in every match.
+    query = query.and(x.suffixes().cross(y.prefixes()).trigrams());
+
+    RegexInfo {
+        can_empty: x.can_empty && y.can_empty,
+        exact: None,
+        prefix,
+        suffix,
+        query,
+    }
+}
+
+fn alternate(mut x: RegexInfo, mut y: RegexInfo) -> RegexInfo {
+    let can_empty = x.can_empty || y.can_empty;
+
+    if let (Some(x_exact), Some(y_exact)) = (&x.exact, &y.exact) {
+        let exact = x_exact.union(y_exact);
+        return RegexInfo {
+            can_empty,
+            query: x.query.or(y.query),
+            ..RegexInfo::exact(exact)
+        };
+    }
+
+    x.add_exact();
+    y.add_exact();
+    RegexInfo {
+        can_empty,
+        exact: None,
+        prefix: x.prefix.union(&y.prefix),
+        suffix: x.suffix.union(&y.suffix),
+        query: x.query.or(y.query),
+    }
+}
+
+/// Expression repeated at least once.
+fn plus(mut x: RegexInfo) -> RegexInfo {
+    x.add_exact();
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regexp::*;
+
+    fn ngram(s: &str) -> NgramQuery {
+        NgramQuery::Ngram(Ngram::from(s))
+    }
+
+    #[test]
+    fn literal() {
+        assert_eq!(
+            NgramQuery::from_regex("merch").unwrap(),
+            NgramQuery::And(vec![ngram("mer"), ngram("erc"), ngram("rch")])
+        );
+        assert_eq!(NgramQuery::from_regex("ab").unwrap(), NgramQuery::All);
+    }
+
+    #[test]
+    fn alternation() {
+        assert_eq!(
+            NgramQuery::from_regex("abc|def").unwrap(),
+            NgramQuery::Or(vec![ngram("abc"), ngram("def")])
+        );
+    }
+
+    #[test]
+    fn repetition() {
+        assert_eq!(
+            NgramQuery::from_regex("abc.*def").unwrap(),
+            NgramQuery::And(vec![ngram("abc"), ngram("def")])
+        );
+        assert_eq!(
+            NgramQuery::from_regex("arguments?").unwrap(),
+            NgramQuery::And(vec![
+                ngram("arg"),
+                ngram("rgu"),
+                ngram("gum"),
+                ngram("ume"),
+                ngram("men"),
+                ngram("ent"),
+            ])
+        );
+        assert_eq!(NgramQuery::from_regex(".*").unwrap(), NgramQuery::All);
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(
+            NgramQuery::from_regex("ab[cd]").unwrap(),
+            NgramQuery::Or(vec![ngram("abc"), ngram("abd")])
+        );
+        assert_eq!(NgramQuery::from_regex("a\\wb").unwrap(), NgramQuery::All);
+    }
+}
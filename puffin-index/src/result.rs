@@ -0,0 +1,248 @@
+use crate::search::file::Kind as DocumentKind;
+use crate::search::symbol::Kind as SymbolKind;
+use crate::search::{Branch, Commit};
+use regex::Regex;
+use std::ops::Range;
+
+/// SearchResult holds the files matching a query, best first.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SearchResult {
+    pub files: Vec<FileMatch>,
+    /// Whether files or line matches were left out because of the limits of
+    /// the `SearchOptions`.
+    pub truncated: bool,
+}
+
+impl SearchResult {
+    /// Groups the files by repository. Repositories are ordered by their
+    /// best file, files keep their order within a repository.
+    pub fn by_repository(&self) -> Vec<(&str, Vec<&FileMatch>)> {
+        let mut groups: Vec<(&str, Vec<&FileMatch>)> = Vec::new();
+        for file in self.files.iter() {
+            match groups.iter_mut().find(|(name, _)| *name == file.repository) {
+                Some((_, files)) => files.push(file),
+                None => groups.push((&file.repository, vec![file])),
+            }
+        }
+        groups
+    }
+}
+
+/// FileMatch is a single file matching the search query.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileMatch {
+    /// Path of the file, or id of the commit for commit messages.
+    pub filename: String,
+    pub file_type: String,
+    /// Whether the match is a file, or a commit message or diff of the history.
+    pub kind: DocumentKind,
+    /// Commit of the history documents.
+    pub commit: Option<Commit>,
+    /// Name of the repository the file belongs to.
+    pub repository: String,
+    /// Branches containing the file, if it was indexed from git objects.
+    pub branches: Vec<Branch>,
+    /// Link to the file, if its repository has a URL template.
+    pub url: Option<String>,
+    /// Relevance of the file for the query, results are sorted by descending score.
+    pub score: f64,
+    /// Lines of the file that matched the content part of the query, in order.
+    /// Empty if only file level filters such as `file:` or `lang:` were used.
+    pub line_matches: Vec<LineMatch>,
+    /// Definitions that matched the `sym:` part of the query, in order.
+    pub symbols: Vec<SymbolMatch>,
+}
+
+/// SymbolMatch is a definition whose name matched the query.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SymbolMatch {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Line number of the definition, starting at 1.
+    pub line_number: usize,
+    /// Byte range of the name within the file.
+    pub byte_range: Range<usize>,
+}
+
+/// LineMatch is a line containing at least one match.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LineMatch {
+    /// Line number, starting at 1.
+    pub line_number: usize,
+    /// Byte offset of the start of the line within the file.
+    pub line_start: usize,
+    /// Text of the line, without the line terminator.
+    pub line: String,
+    /// Matches on the line, ordered by their start.
+    pub matches: Vec<Match>,
+    /// Byte ranges of the line to highlight, covering all of its matches.
+    /// Overlapping and adjacent matches are merged, ranges are ordered.
+    pub highlights: Vec<Range<usize>>,
+    /// Lines preceding the line, up to the number of context lines asked for.
+    pub before: Vec<String>,
+    /// Lines following the line, up to the number of context lines asked for.
+    pub after: Vec<String>,
+}
+
+/// Match is a single occurrence of the query in the file content.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Match {
+    /// Byte range of the match within the file.
+    pub byte_range: Range<usize>,
+    /// Char range of the match within the line, starting at 0. Matches
+    /// spanning multiple lines end at the end of their first line.
+    pub column_range: Range<usize>,
+}
+
+/// Finds all occurrences of the patterns in the content and groups them by
+/// line, along with the names of the matching symbols.
+pub(crate) fn line_matches(
+    content: &str,
+    patterns: &[Regex],
+    symbols: &[SymbolMatch],
+) -> Vec<LineMatch> {
+    let mut ranges: Vec<Range<usize>> = patterns
+        .iter()
+        .flat_map(|re| re.find_iter(content))
+        .filter(|m| !m.is_empty())
+        .map(|m| m.range())
+        .chain(symbols.iter().map(|s| s.byte_range.clone()))
+        .collect();
+    ranges.sort_by_key(|r| (r.start, r.end));
+    ranges.dedup();
+
+    let mut result: Vec<LineMatch> = Vec::new();
+    let mut line_number = 1;
+    let mut line_start = 0;
+    for range in ranges {
+        // Ranges are sorted, so we only ever need to move forward.
+        while let Some(newline) = content[line_start..].find('\n') {
+            if line_start + newline >= range.start {
+                break;
+            }
+            line_start += newline + 1;
+            line_number += 1;
+        }
+        let line_end = content[line_start..]
+            .find('\n')
+            .map_or(content.len(), |newline| line_start + newline);
+        let line = content[line_start..line_end].trim_end_matches('\r');
+
+        let column = |offset: usize| content[line_start..offset.min(line_end)].chars().count();
+        let m = Match {
+            column_range: column(range.start)..column(range.end),
+            byte_range: range,
+        };
+
+        let highlight = range_in_line(&m.byte_range, line_start, line.len());
+        match result.last_mut() {
+            Some(last) if last.line_number == line_number => {
+                last.matches.push(m);
+                match last.highlights.last_mut() {
+                    Some(prev) if prev.end >= highlight.start => {
+                        prev.end = prev.end.max(highlight.end)
+                    }
+                    _ => last.highlights.push(highlight),
+                }
+            }
+            _ => result.push(LineMatch {
+                line_number,
+                line_start,
+                line: line.to_string(),
+                matches: vec![m],
+                highlights: vec![highlight],
+                before: Vec::new(),
+                after: Vec::new(),
+            }),
+        }
+    }
+    result
+}
+
+/// Returns the byte range of the line covered by the range of the file.
+fn range_in_line(range: &Range<usize>, line_start: usize, line_len: usize) -> Range<usize> {
+    let start = (range.start - line_start).min(line_len);
+    let end = (range.end - line_start).min(line_len);
+    start..end
+}
+
+/// Sets the `context` lines before and after every line match.
+pub(crate) fn add_context(content: &str, line_matches: &mut [LineMatch], context: usize) {
+    let lines: Vec<&str> = content.lines().collect();
+    for m in line_matches.iter_mut() {
+        let index = m.line_number - 1;
+        let to_strings = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect();
+        m.before = to_strings(&lines[index.saturating_sub(context)..index]);
+        m.after = to_strings(
+            &lines[(index + 1).min(lines.len())..(index + 1 + context).min(lines.len())],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::result::*;
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_line_matches() {
+        let content = "fn main() {\n    let mérchant = merchant();\n}\n";
+        let patterns = vec![Regex::new("merchant").unwrap()];
+        let symbols = vec![SymbolMatch {
+            name: "main".into(),
+            kind: SymbolKind::Function,
+            line_number: 1,
+            byte_range: 3..7,
+        }];
+
+        assert_eq!(
+            line_matches(content, &patterns, &symbols),
+            vec![
+                LineMatch {
+                    line_number: 1,
+                    line_start: 0,
+                    line: "fn main() {".into(),
+                    matches: vec![Match {
+                        byte_range: 3..7,
+                        column_range: 3..7,
+                    }],
+                    highlights: vec![3..7],
+                    before: vec![],
+                    after: vec![],
+                },
+                LineMatch {
+                    line_number: 2,
+                    line_start: 12,
+                    line: "    let mérchant = merchant();".into(),
+                    matches: vec![Match {
+                        byte_range: 32..40,
+                        column_range: 19..27,
+                    }],
+                    highlights: vec![20..28],
+                    before: vec![],
+                    after: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_highlights_and_context() {
+        let content = "one\ntwo\nfoobar baz\nfour\n";
+        let patterns = vec![
+            Regex::new("foo").unwrap(),
+            Regex::new("oba").unwrap(),
+            Regex::new("bar").unwrap(),
+            Regex::new("baz").unwrap(),
+        ];
+
+        let mut matches = line_matches(content, &patterns, &[]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].matches.len(), 4);
+        assert_eq!(matches[0].highlights, vec![0..6, 7..10]);
+
+        add_context(content, &mut matches, 2);
+        assert_eq!(matches[0].before, vec!["one", "two"]);
+        assert_eq!(matches[0].after, vec!["four"]);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::result::LineMatch;
+use regex::Regex;
+use std::path::Path;
+
+/// Files with a match in their name, such as `merchant.go` for `merchant`.
+const FILENAME_MATCH: f64 = 400.0;
+/// Files with a match on the name of a definition, such as `fn merchant()`.
+const DEFINITION_MATCH: f64 = 300.0;
+/// Files where all matches are whole words, partial share otherwise.
+const WORD_MATCH: f64 = 100.0;
+/// Weight of the log scaled number of matches.
+const FREQUENCY: f64 = 25.0;
+/// Score of the shortest files, decreasing with the log scaled line count.
+const SHORT_FILE: f64 = 50.0;
+/// Factor applied to vendored and generated files.
+const GENERATED_FACTOR: f64 = 0.1;
+
+/// Number of lines at the start of a file searched for generated code markers.
+const GENERATED_HEADER_LINES: usize = 10;
+
+/// Path components of vendored dependencies and build output.
+const VENDORED_DIRS: &[&str] = &[
+    "vendor",
+    "vendored",
+    "third_party",
+    "third-party",
+    "node_modules",
+    "bower_components",
+    "dist",
+    "target",
+    "Pods",
+];
+
+/// Suffixes of generated or minified files.
+const GENERATED_SUFFIXES: &[&str] = &[
+    ".min.js",
+    ".min.css",
+    ".pb.go",
+    ".pb.cc",
+    ".pb.h",
+    "_pb2.py",
+    ".generated.cs",
+    ".g.dart",
+    ".lock",
+];
+
+lazy_static::lazy_static! {
+    static ref DEFINITION: Regex = Regex::new(
+        r"\b(?:fn|func|def|class|struct|enum|union|trait|interface|type|impl|const|static|let|var|val|function|mod|module|macro_rules!)\s+(?:\([^)]*\)\s*)?([\p{L}_][\p{L}\p{N}_]*)"
+    ).unwrap();
+    static ref GENERATED_MARKER: Regex =
+        Regex::new(r"(?i)(?:code generated .* do not edit|@generated|auto-?generated|do not edit)").unwrap();
+}
+
+/// Scores the file for ranking, higher scores go first. `patterns` are the
+/// content patterns of the query that produced the line matches.
+pub(crate) fn score(
+    filename: &str,
+    content: &str,
+    line_matches: &[LineMatch],
+    patterns: &[Regex],
+) -> f64 {
+    let mut score = 0.0;
+
+    let basename = Path::new(filename)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(filename);
+    if patterns.iter().any(|re| re.is_match(basename)) {
+        score += FILENAME_MATCH;
+    }
+
+    let matches = line_matches.iter().flat_map(|line| {
+        line.matches.iter().map(move |m| {
+            let start = m.byte_range.start - line.line_start;
+            let end = (m.byte_range.end - line.line_start).min(line.line.len());
+            (line.line.as_str(), start..end)
+        })
+    });
+    let mut count = 0;
+    let mut words = 0;
+    let mut definition = false;
+    for (line, range) in matches {
+        count += 1;
+        if is_word(line, &range) {
+            words += 1;
+        }
+        definition = definition || is_definition(line, &range);
+    }
+    if definition {
+        score += DEFINITION_MATCH;
+    }
+    if count > 0 {
+        score += WORD_MATCH * words as f64 / count as f64;
+        score += FREQUENCY * (count as f64).ln_1p();
+    }
+
+    let lines = content.lines().count();
+    score += SHORT_FILE / (1.0 + (lines as f64).ln_1p());
+
+    if is_vendored(filename) || is_generated(content) {
+        score *= GENERATED_FACTOR;
+    }
+    score
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether the match in the line isn't part of a longer word.
+fn is_word(line: &str, range: &std::ops::Range<usize>) -> bool {
+    let before = line[..range.start].chars().next_back();
+    let after = line[range.end..].chars().next();
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+/// Whether the match covers the name of a definition in the line.
+fn is_definition(line: &str, range: &std::ops::Range<usize>) -> bool {
+    DEFINITION
+        .captures_iter(line)
+        .filter_map(|captures| captures.get(1))
+        .any(|name| name.start() <= range.start && range.end <= name.end())
+}
+
+fn is_vendored(filename: &str) -> bool {
+    Path::new(filename).components().any(|component| {
+        component
+            .as_os_str()
+            .to_str()
+            .is_some_and(|name| VENDORED_DIRS.contains(&name))
+    }) || GENERATED_SUFFIXES
+        .iter()
+        .any(|suffix| filename.ends_with(suffix))
+}
+
+fn is_generated(content: &str) -> bool {
+    content
+        .lines()
+        .take(GENERATED_HEADER_LINES)
+        .any(|line| GENERATED_MARKER.is_match(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::result::line_matches;
+    use crate::score::*;
+
+    fn score_of(filename: &str, content: &str, pattern: &str) -> f64 {
+        let patterns = vec![Regex::new(pattern).unwrap()];
+        score(
+            filename,
+            content,
+            &line_matches(content, &patterns, &[]),
+            &patterns,
+        )
+    }
+
+    #[test]
+    fn test_score() {
+        let usage = score_of("src/pay.rs", "let m = merchant();\n", "merchant");
+        let definition = score_of("src/pay.rs", "fn merchant() {}\n", "merchant");
+        let partial = score_of("src/pay.rs", "let m = merchants();\n", "merchant");
+        let filename = score_of("src/merchant.rs", "let m = merchant();\n", "merchant");
+        let vendored = score_of("vendor/pay.rs", "fn merchant() {}\n", "merchant");
+        let generated = score_of(
+            "src/pay.rs",
+            "// Code generated by protoc. DO NOT EDIT.\nfn merchant() {}\n",
+            "merchant",
+        );
+        let frequent = score_of("src/pay.rs", "merchant(merchant)\n", "merchant");
+        let long = score_of(
+            "src/pay.rs",
+            &format!("let m = merchant();\n{}", "// ...\n".repeat(500)),
+            "merchant",
+        );
+
+        assert!(definition > usage);
+        assert!(usage > partial);
+        assert!(filename > definition);
+        assert!(vendored < usage);
+        assert!(generated < usage);
+        assert!(frequent > usage);
+        assert!(long < usage);
+    }
+
+    #[test]
+    fn test_is_definition() {
+        for (line, name) in [
+            ("pub fn merchant() {", "merchant"),
+            ("func (s *Server) Merchant() error {", "Merchant"),
+            ("class Merchant:", "Merchant"),
+            ("type Merchant struct {", "Merchant"),
+            ("const MERCHANT: u32 = 1;", "MERCHANT"),
+        ] {
+            let start = line.find(name).unwrap();
+            assert!(
+                is_definition(line, &(start..start + name.len())),
+                "{}",
+                line
+            );
+        }
+        assert!(!is_definition("let m = merchant();", &(8..16)));
+    }
+}
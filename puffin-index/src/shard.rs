@@ -0,0 +1,680 @@
+use crate::history::History;
+use crate::lang;
+use crate::ngram::split_ngrams;
+use crate::options::SearchOptions;
+use crate::positions::{self, Positions};
+use crate::postings::FileIds;
+use crate::reader::{self, Grams, IndexReader};
+use crate::result::{FileMatch, SearchResult};
+use crate::{search, FileId};
+use memmap2::Mmap;
+use prost::Message;
+use puffin_query::QueryNode;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"PUFSHARD";
+// Version 2 switched to case folded n-grams, version 3 added symbols.
+const VERSION: u32 = 3;
+const HEADER_SIZE: usize = 20;
+const SECTION_ENTRY_SIZE: usize = 16;
+
+/// Header flag set when the shard holds positions of the content trigrams.
+const FLAG_POSITIONS: u32 = 1;
+
+/// Sections of a shard, in the order they are stored in the file.
+#[derive(Clone, Copy)]
+enum Section {
+    /// Dictionary of the content n-grams pointing into `ContentPostings`.
+    ContentNgrams,
+    ContentPostings,
+    /// Dictionary of the path n-grams pointing into `PathPostings`.
+    PathNgrams,
+    PathPostings,
+    /// Dictionary of the symbol n-grams pointing into `SymbolPostings`.
+    SymbolNgrams,
+    SymbolPostings,
+    /// Dictionary of the content trigrams pointing into `Positions`.
+    PositionNgrams,
+    Positions,
+    /// Dictionary of the file ids pointing into `DocRecords`.
+    Docs,
+    DocRecords,
+    /// LZ4 compressed file contents.
+    Contents,
+}
+
+const SECTIONS_COUNT: usize = Section::Contents as usize + 1;
+
+/// Returns the dictionary and postings sections of the n-gram table.
+fn posting_sections(grams: Grams) -> (Section, Section) {
+    match grams {
+        Grams::Content => (Section::ContentNgrams, Section::ContentPostings),
+        Grams::Path => (Section::PathNgrams, Section::PathPostings),
+        Grams::Symbol => (Section::SymbolNgrams, Section::SymbolPostings),
+    }
+}
+
+/*
+Shard layout:
+[header][section table][sections]...
+
+Header:
+[magic][version][flags][sections count]
+<8 byte><4 byte><4 byte><---4 byte----->
+
+Section table entry, one for every section:
+[offset][length]
+<8 byte><8 byte>
+
+Doc record, file content is left out of the prost record:
+[content offset][content length][search::File]
+<----8 byte----><----8 byte----><--rest----->
+*/
+
+/// ShardBuilder collects the documents of a shard in memory and writes them
+/// out as a single file.
+pub(crate) struct ShardBuilder {
+    content_ngrams: BTreeMap<String, FileIds<'static>>,
+    path_ngrams: BTreeMap<String, FileIds<'static>>,
+    symbol_ngrams: BTreeMap<String, FileIds<'static>>,
+    positions: Option<BTreeMap<String, Positions>>,
+    /// Documents without content, along with their compressed content.
+    docs: BTreeMap<FileId, (search::File, Vec<u8>)>,
+}
+
+impl ShardBuilder {
+    pub fn new(with_positions: bool) -> Self {
+        Self {
+            content_ngrams: BTreeMap::new(),
+            path_ngrams: BTreeMap::new(),
+            symbol_ngrams: BTreeMap::new(),
+            positions: with_positions.then(BTreeMap::new),
+            docs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the document to the shard. Adding documents in ascending order of
+    /// their ids keeps appending to the posting lists cheap.
+    pub fn add(&mut self, file_id: &FileId, file: &search::File) {
+        for line in file.content.lines() {
+            for (trigram, _) in split_ngrams(line) {
+                self.content_ngrams
+                    .entry(trigram.to_string())
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+        }
+        for (trigram, _) in split_ngrams(&file.filename) {
+            self.path_ngrams
+                .entry(trigram.to_string())
+                .or_default()
+                .insert(file_id.clone());
+        }
+        for symbol in file.symbols.iter() {
+            for (trigram, _) in split_ngrams(&symbol.name) {
+                self.symbol_ngrams
+                    .entry(trigram.to_string())
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+        }
+        if let Some(table) = self.positions.as_mut() {
+            for (trigram, offsets) in positions::collect_positions(&file.content) {
+                table
+                    .entry(trigram)
+                    .or_default()
+                    .insert(file_id.clone(), offsets);
+            }
+        }
+
+        let content = lz4_flex::compress_prepend_size(file.content.as_bytes());
+        let meta = search::File {
+            content: String::new(),
+            ..file.clone()
+        };
+        self.docs.insert(file_id.clone(), (meta, content));
+    }
+
+    pub fn write(self, path: &Path) -> io::Result<()> {
+        let mut sections = vec![Vec::new(); SECTIONS_COUNT];
+
+        let mut set_dictionary =
+            |dict: Section, values: Section, entries: Vec<(Vec<u8>, Vec<u8>)>| {
+                let (dict_bytes, values_bytes) = write_dictionary(entries);
+                sections[dict as usize] = dict_bytes;
+                sections[values as usize] = values_bytes;
+            };
+        set_dictionary(
+            Section::ContentNgrams,
+            Section::ContentPostings,
+            ngram_entries(self.content_ngrams),
+        );
+        set_dictionary(
+            Section::PathNgrams,
+            Section::PathPostings,
+            ngram_entries(self.path_ngrams),
+        );
+        set_dictionary(
+            Section::SymbolNgrams,
+            Section::SymbolPostings,
+            ngram_entries(self.symbol_ngrams),
+        );
+        let flags = match self.positions {
+            Some(positions) => {
+                set_dictionary(
+                    Section::PositionNgrams,
+                    Section::Positions,
+                    positions
+                        .into_iter()
+                        .map(|(trigram, positions)| (trigram.into_bytes(), positions.into()))
+                        .collect(),
+                );
+                FLAG_POSITIONS
+            }
+            None => 0,
+        };
+
+        let mut contents = Vec::new();
+        let mut docs = Vec::new();
+        for (file_id, (meta, content)) in self.docs {
+            let mut record = Vec::new();
+            record.extend(u64::to_le_bytes(contents.len() as u64));
+            record.extend(u64::to_le_bytes(content.len() as u64));
+            meta.encode(&mut record)?;
+            contents.extend(content);
+            docs.push((doc_key(&file_id).to_vec(), record));
+        }
+        set_dictionary(Section::Docs, Section::DocRecords, docs);
+        sections[Section::Contents as usize] = contents;
+
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&u32::to_le_bytes(VERSION))?;
+        writer.write_all(&u32::to_le_bytes(flags))?;
+        writer.write_all(&u32::to_le_bytes(SECTIONS_COUNT as u32))?;
+        let mut offset = (HEADER_SIZE + SECTIONS_COUNT * SECTION_ENTRY_SIZE) as u64;
+        for section in sections.iter() {
+            writer.write_all(&u64::to_le_bytes(offset))?;
+            writer.write_all(&u64::to_le_bytes(section.len() as u64))?;
+            offset += section.len() as u64;
+        }
+        for section in sections.iter() {
+            writer.write_all(section)?;
+        }
+        writer.flush()?;
+        fs::rename(tmp, path)
+    }
+}
+
+fn ngram_entries(ngrams: BTreeMap<String, FileIds<'static>>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    ngrams
+        .into_iter()
+        .map(|(ngram, ids)| (ngram.into_bytes(), ids.into()))
+        .collect()
+}
+
+/// File ids are stored big endian, so their byte order matches the numeric one.
+fn doc_key(file_id: &FileId) -> [u8; 4] {
+    u32::to_be_bytes(file_id.0)
+}
+
+/// ShardReader serves searches from a memory-mapped shard. Only the doc
+/// records are read upfront, the dictionaries and postings are accessed in
+/// place and the contents are decompressed when a document gets verified.
+pub struct ShardReader {
+    mmap: Mmap,
+    flags: u32,
+    sections: Vec<Range<usize>>,
+    /// Documents without content, along with the range of their compressed
+    /// content in the contents section.
+    docs: BTreeMap<FileId, (search::File, Range<usize>)>,
+    languages: BTreeMap<&'static str, BTreeSet<FileId>>,
+    repositories: BTreeMap<String, BTreeSet<FileId>>,
+    branches: BTreeMap<String, BTreeSet<FileId>>,
+    history: History,
+}
+
+impl ShardReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: shards are never modified in place, a new shard is written
+        // to a temporary file and renamed over the old one.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let header = mmap
+            .get(0..HEADER_SIZE)
+            .ok_or_else(|| invalid_data("truncated shard header"))?;
+        if &header[0..8] != MAGIC {
+            return Err(invalid_data("not a shard"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(invalid_data("unsupported shard version"));
+        }
+        let flags = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let count = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+        if count != SECTIONS_COUNT {
+            return Err(invalid_data("unexpected number of shard sections"));
+        }
+
+        let table = mmap
+            .get(HEADER_SIZE..HEADER_SIZE + count * SECTION_ENTRY_SIZE)
+            .ok_or_else(|| invalid_data("truncated shard section table"))?;
+        let sections = table
+            .chunks(SECTION_ENTRY_SIZE)
+            .map(|entry| {
+                let offset = u64::from_le_bytes(entry[0..8].try_into().unwrap()) as usize;
+                let len = u64::from_le_bytes(entry[8..16].try_into().unwrap()) as usize;
+                match offset.checked_add(len) {
+                    Some(end) if end <= mmap.len() => Ok(offset..end),
+                    _ => Err(invalid_data("shard section out of bounds")),
+                }
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut shard = Self {
+            mmap,
+            flags,
+            sections,
+            docs: BTreeMap::new(),
+            languages: BTreeMap::new(),
+            repositories: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            history: History::default(),
+        };
+
+        let mut docs = BTreeMap::new();
+        let mut languages: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+        let mut repositories: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+        let mut branches: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+        let mut history = History::default();
+        for (key, record) in shard.dictionary(Section::Docs, Section::DocRecords)?.iter() {
+            let (key, header, meta) = match (key.try_into(), record.get(0..16), record.get(16..)) {
+                (Ok(key), Some(header), Some(meta)) => (key, header, meta),
+                _ => return Err(invalid_data("corrupted doc record")),
+            };
+            let file_id = FileId(u32::from_be_bytes(key));
+            let start = u64::from_le_bytes(header[0..8].try_into().unwrap()) as usize;
+            let len = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize;
+            let meta = search::File::decode(meta)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+            if let Some(language) = lang::find(&meta.file_type) {
+                languages
+                    .entry(language.name)
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+            if let Some(repository) = meta.repository.as_ref() {
+                repositories
+                    .entry(repository.name.clone())
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+            for branch in meta.branches.iter() {
+                branches
+                    .entry(branch.name.clone())
+                    .or_default()
+                    .insert(file_id.clone());
+            }
+            history.add(&file_id, &meta);
+            docs.insert(file_id, (meta, start..start + len));
+        }
+        // Posting lists and positions are read lazily while searching,
+        // corrupted ones are better caught here.
+        for grams in [Grams::Content, Grams::Path, Grams::Symbol] {
+            let (dict, values) = posting_sections(grams);
+            for (_, postings) in shard.dictionary(dict, values)?.iter() {
+                FileIds::from_bytes(postings)?;
+            }
+        }
+        for (_, positions) in shard
+            .dictionary(Section::PositionNgrams, Section::Positions)?
+            .iter()
+        {
+            Positions::from_bytes(positions)?;
+        }
+
+        shard.docs = docs;
+        shard.languages = languages;
+        shard.repositories = repositories;
+        shard.branches = branches;
+        shard.history = history;
+        Ok(shard)
+    }
+
+    /// Returns all files matching the query, best first.
+    pub fn search(&self, query: QueryNode) -> Vec<FileMatch> {
+        self.search_with_options(query, &SearchOptions::default())
+            .files
+    }
+
+    /// Returns the files matching the query within the limits of the options.
+    pub fn search_with_options(&self, query: QueryNode, options: &SearchOptions) -> SearchResult {
+        reader::search(self, query, options)
+    }
+
+    /// Returns paths of all files in the shard that contain the given fragment.
+    pub fn find_files(&self, fragment: &str) -> Vec<String> {
+        reader::find_files(self, fragment)
+    }
+
+    fn section(&self, section: Section) -> &[u8] {
+        &self.mmap[self.sections[section as usize].clone()]
+    }
+
+    fn dictionary(&self, dict: Section, values: Section) -> io::Result<Dictionary<'_>> {
+        Dictionary::new(self.section(dict), self.section(values))
+    }
+
+    /// Looks up the key in the dictionary, treating a corrupted dictionary as
+    /// missing the key.
+    fn lookup(&self, dict: Section, values: Section, key: &[u8]) -> Option<&[u8]> {
+        match self.dictionary(dict, values) {
+            Ok(dictionary) => dictionary.get(key),
+            Err(err) => {
+                log::warn!("failed to read shard dictionary: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl IndexReader for ShardReader {
+    fn postings(&self, grams: Grams, ngram: &str) -> FileIds<'_> {
+        let (dict, values) = posting_sections(grams);
+        let Some(bytes) = self.lookup(dict, values, ngram.as_bytes()) else {
+            return FileIds::default();
+        };
+        FileIds::from_bytes(bytes).unwrap_or_else(|err| {
+            log::warn!("failed to read posting list of {:?}: {}", ngram, err);
+            FileIds::default()
+        })
+    }
+
+    fn has_positions(&self) -> bool {
+        self.flags & FLAG_POSITIONS != 0
+    }
+
+    fn positions(&self, trigram: &str) -> Positions {
+        self.lookup(
+            Section::PositionNgrams,
+            Section::Positions,
+            trigram.as_bytes(),
+        )
+        .map_or_else(Positions::default, |bytes| {
+            Positions::from_bytes(bytes).unwrap_or_else(|err| {
+                log::warn!("failed to read positions of {:?}: {}", trigram, err);
+                Positions::default()
+            })
+        })
+    }
+
+    fn file(&self, file_id: &FileId) -> Option<Cow<'_, search::File>> {
+        let (meta, range) = self.docs.get(file_id)?;
+        let content = self
+            .section(Section::Contents)
+            .get(range.clone())
+            .and_then(|bytes| lz4_flex::decompress_size_prepended(bytes).ok())
+            .and_then(|bytes| String::from_utf8(bytes).ok());
+        let Some(content) = content else {
+            log::warn!("failed to read content of {:?}", meta.filename);
+            return None;
+        };
+        Some(Cow::Owned(search::File {
+            content,
+            ..meta.clone()
+        }))
+    }
+
+    fn filename(&self, file_id: &FileId) -> Option<&str> {
+        self.docs
+            .get(file_id)
+            .map(|(meta, _)| meta.filename.as_str())
+    }
+
+    fn file_ids(&self) -> Box<dyn Iterator<Item = FileId> + '_> {
+        Box::new(self.docs.keys().cloned())
+    }
+
+    fn language(&self, name: &str) -> BTreeSet<FileId> {
+        self.languages.get(name).cloned().unwrap_or_default()
+    }
+
+    fn repositories(&self) -> &BTreeMap<String, BTreeSet<FileId>> {
+        &self.repositories
+    }
+
+    fn branches(&self) -> &BTreeMap<String, BTreeSet<FileId>> {
+        &self.branches
+    }
+
+    fn history(&self) -> &History {
+        &self.history
+    }
+}
+
+/// Dictionary is a sorted list of keys, each pointing to its value in a
+/// separate section. Lookups binary search the keys in place.
+struct Dictionary<'a> {
+    len: usize,
+    entries: &'a [u8],
+    keys: &'a [u8],
+    values: &'a [u8],
+}
+
+/*
+Dictionary layout:
+[keys count][entries][keys]
+<-4 byte---><-12 bytes * keys count->
+
+Entry, the start offsets are the ends of the previous entry:
+[key end][value end]
+<4 byte-><-8 byte-->
+*/
+const DICTIONARY_ENTRY_SIZE: usize = 12;
+
+impl<'a> Dictionary<'a> {
+    fn new(dict: &'a [u8], values: &'a [u8]) -> io::Result<Self> {
+        if dict.is_empty() {
+            return Ok(Self {
+                len: 0,
+                entries: &[],
+                keys: &[],
+                values,
+            });
+        }
+        let len = dict
+            .get(0..4)
+            .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid_data("truncated dictionary"))?;
+        let entries_end = 4 + len * DICTIONARY_ENTRY_SIZE;
+        if dict.len() < entries_end {
+            return Err(invalid_data("truncated dictionary"));
+        }
+        Ok(Self {
+            len,
+            entries: &dict[4..entries_end],
+            keys: &dict[entries_end..],
+            values,
+        })
+    }
+
+    /// Returns end offsets of the key and value of the entry.
+    fn ends(&self, i: usize) -> (usize, usize) {
+        let entry = &self.entries[i * DICTIONARY_ENTRY_SIZE..(i + 1) * DICTIONARY_ENTRY_SIZE];
+        (
+            u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize,
+            u64::from_le_bytes(entry[4..12].try_into().unwrap()) as usize,
+        )
+    }
+
+    fn entry(&self, i: usize) -> (&'a [u8], &'a [u8]) {
+        let (key_start, value_start) = match i {
+            0 => (0, 0),
+            _ => self.ends(i - 1),
+        };
+        let (key_end, value_end) = self.ends(i);
+        (
+            self.keys.get(key_start..key_end).unwrap_or_default(),
+            self.values.get(value_start..value_end).unwrap_or_default(),
+        )
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&'a [u8]> {
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (current, value) = self.entry(mid);
+            match current.cmp(key) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(value),
+            }
+        }
+        None
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + '_ {
+        (0..self.len).map(|i| self.entry(i))
+    }
+}
+
+/// Encodes entries, sorted by their keys, into the dictionary and values sections.
+fn write_dictionary(entries: Vec<(Vec<u8>, Vec<u8>)>) -> (Vec<u8>, Vec<u8>) {
+    let mut dict = Vec::with_capacity(4 + entries.len() * DICTIONARY_ENTRY_SIZE);
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    dict.extend(u32::to_le_bytes(entries.len() as u32));
+    for (key, value) in entries {
+        keys.extend(key);
+        values.extend(value);
+        dict.extend(u32::to_le_bytes(keys.len() as u32));
+        dict.extend(u64::to_le_bytes(values.len() as u64));
+    }
+    dict.extend(keys);
+    (dict, values)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shard::*;
+    use crate::testutil::Fixture;
+    use crate::{lang, symbols};
+
+    fn file(filename: &str, content: &str, file_type: &str) -> search::File {
+        search::File {
+            filename: filename.into(),
+            content: content.into(),
+            file_type: file_type.into(),
+            symbols: symbols::extract(lang::find(file_type), content),
+            ..Default::default()
+        }
+    }
+
+    fn filenames(matches: Vec<FileMatch>) -> Vec<String> {
+        matches.into_iter().map(|m| m.filename).collect()
+    }
+
+    #[test]
+    fn test_shard() {
+        let fixture = Fixture::new();
+        let path = fixture.path("shard");
+
+        let mut builder = ShardBuilder::new(true);
+        builder.add(
+            &FileId(0),
+            &file("src/main.rs", "fn main() {\n    run();\n}\n", "Rust"),
+        );
+        builder.add(
+            &FileId(2),
+            &file("cmd/main.go", "package main\n\nfunc run() {}\n", "Go"),
+        );
+        builder.write(Path::new(&path)).unwrap();
+
+        let shard = ShardReader::open(&path).unwrap();
+        // The definition of run ranks above its call.
+        assert_eq!(
+            filenames(shard.search(QueryNode::new("run"))),
+            vec!["cmd/main.go", "src/main.rs"]
+        );
+        assert_eq!(
+            filenames(shard.search(QueryNode::new("run AND lang:go"))),
+            vec!["cmd/main.go"]
+        );
+        assert_eq!(
+            filenames(shard.search(QueryNode::new("NOT /fn\\s+main/"))),
+            vec!["cmd/main.go"]
+        );
+        assert_eq!(
+            filenames(shard.search(QueryNode::new("sym:run"))),
+            vec!["cmd/main.go"]
+        );
+        assert_eq!(shard.find_files("main.rs"), vec!["src/main.rs"]);
+        let matches = shard.search(QueryNode::new("\"func run\""));
+        assert_eq!(matches[0].line_matches[0].line_number, 3);
+    }
+
+    #[test]
+    fn test_corrupted_postings() {
+        let fixture = Fixture::new();
+        let path = fixture.path("shard");
+        let mut builder = ShardBuilder::new(false);
+        builder.add(&FileId(0), &file("main.go", "func run() {}\n", "Go"));
+        builder.write(Path::new(&path)).unwrap();
+
+        // Claim more blocks than the first posting list holds.
+        let mut bytes = fs::read(&path).unwrap();
+        let entry = HEADER_SIZE + Section::ContentPostings as usize * SECTION_ENTRY_SIZE;
+        let offset = u64::from_le_bytes(bytes[entry..entry + 8].try_into().unwrap()) as usize;
+        bytes[offset..offset + 2].copy_from_slice(&[0x01, 0x7f]);
+        fs::write(&path, bytes).unwrap();
+
+        let err = ShardReader::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_corrupted_positions() {
+        let fixture = Fixture::new();
+        let path = fixture.path("shard");
+        let mut builder = ShardBuilder::new(true);
+        builder.add(&FileId(0), &file("main.go", "func run() {}\n", "Go"));
+        builder.write(Path::new(&path)).unwrap();
+
+        // Claim more offsets than the first positions entry holds.
+        let mut bytes = fs::read(&path).unwrap();
+        let entry = HEADER_SIZE + Section::Positions as usize * SECTION_ENTRY_SIZE;
+        let offset = u64::from_le_bytes(bytes[entry..entry + 8].try_into().unwrap()) as usize;
+        bytes[offset + 4..offset + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let err = ShardReader::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let (dict, values) = write_dictionary(vec![
+            (b"abc".to_vec(), b"1".to_vec()),
+            (b"abd".to_vec(), Vec::new()),
+            (b"xyz".to_vec(), b"333".to_vec()),
+        ]);
+        let dictionary = Dictionary::new(&dict, &values).unwrap();
+        assert_eq!(dictionary.get(b"abc"), Some(&b"1"[..]));
+        assert_eq!(dictionary.get(b"abd"), Some(&b""[..]));
+        assert_eq!(dictionary.get(b"xyz"), Some(&b"333"[..]));
+        assert_eq!(dictionary.get(b"abe"), None);
+        assert_eq!(dictionary.iter().count(), 3);
+    }
+}
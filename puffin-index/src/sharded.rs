@@ -0,0 +1,314 @@
+use crate::options::SearchOptions;
+use crate::reader;
+use crate::result::{FileMatch, SearchResult};
+use crate::shard::{ShardBuilder, ShardReader};
+use crate::{search, FileId, Index};
+use puffin_query::QueryNode;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+/// Bytes of content a shard holds at most by default.
+const DEFAULT_MAX_SHARD_SIZE: u64 = 100 << 20;
+
+/// ShardedIndex is a directory of shards, one per repository unless the
+/// repository is larger than the maximum shard size. Queries are evaluated on
+/// every shard in parallel and the best files of all shards make the result.
+/// The shards of a repository are replaced without touching the others.
+///
+/// Shards are named `<repository>.<part>.shard`, with the characters of the
+/// repository name other than ASCII letters, digits, `-` and `_` escaped.
+pub struct ShardedIndex {
+    dir: PathBuf,
+    /// Shards of every repository, by repository name.
+    shards: BTreeMap<String, Vec<ShardReader>>,
+    max_shard_size: u64,
+}
+
+impl ShardedIndex {
+    /// Opens the shards in the directory, creating the directory if needed.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let mut parts: BTreeMap<String, BTreeMap<usize, PathBuf>> = BTreeMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some((name, part)) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(parse_shard_name)
+            else {
+                continue;
+            };
+            parts.entry(name).or_default().insert(part, path);
+        }
+
+        let mut shards = BTreeMap::new();
+        for (name, paths) in parts {
+            let readers = paths
+                .into_values()
+                .map(ShardReader::open)
+                .collect::<io::Result<Vec<_>>>()?;
+            shards.insert(name, readers);
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            shards,
+            max_shard_size: DEFAULT_MAX_SHARD_SIZE,
+        })
+    }
+
+    /// Sets how many bytes of content a shard may hold, larger repositories
+    /// are split over several shards. Defaults to 100 MiB.
+    pub fn set_max_shard_size(&mut self, bytes: u64) {
+        self.max_shard_size = bytes;
+    }
+
+    /// Returns names of the repositories with shards, in order.
+    pub fn repositories(&self) -> impl Iterator<Item = &str> {
+        self.shards.keys().map(String::as_str)
+    }
+
+    /// Writes every repository of the index into shards of its own, replacing
+    /// the shards the repository had.
+    pub fn add(&mut self, index: &Index) -> io::Result<()> {
+        for (name, file_ids) in index.repositories.iter() {
+            let files = file_ids
+                .iter()
+                .filter_map(|file_id| Some((file_id, index.file_meta.get(file_id)?)));
+            self.write_repository(name, files, index.content_positions.is_some())?;
+        }
+        Ok(())
+    }
+
+    /// Removes the shards of the repository.
+    pub fn remove(&mut self, repository: &str) -> io::Result<()> {
+        if let Some(readers) = self.shards.remove(repository) {
+            for part in 0..readers.len() {
+                fs::remove_file(self.shard_path(repository, part))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all files matching the query, best first.
+    pub fn search(&self, query: QueryNode) -> Vec<FileMatch> {
+        self.search_with_options(query, &SearchOptions::default())
+            .files
+    }
+
+    /// Returns the files matching the query within the limits of the
+    /// options. The limits on matches apply to every shard, the number of
+    /// files and the time budget to the whole search.
+    pub fn search_with_options(&self, query: QueryNode, options: &SearchOptions) -> SearchResult {
+        let started = Instant::now();
+        let shards: Vec<&ShardReader> = self.shards.values().flatten().collect();
+        let threads = options.thread_count().min(shards.len()).max(1);
+        let next = AtomicUsize::new(0);
+        let results = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        while let Some(shard) = shards.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            // Shards waiting for a thread get what is left of
+                            // the budget.
+                            let mut options = options.clone();
+                            if let Some(budget) = options.time_budget {
+                                let left = budget.saturating_sub(started.elapsed());
+                                if left.is_zero() {
+                                    results.push(SearchResult {
+                                        files: Vec::new(),
+                                        truncated: true,
+                                    });
+                                    continue;
+                                }
+                                options.time_budget = Some(left);
+                            }
+                            results.push(reader::search(*shard, query.clone(), &options));
+                        }
+                        results
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("search thread panicked"))
+                .collect::<Vec<_>>()
+        });
+        reader::merge(results, options)
+    }
+
+    /// Writes the files into the shards of the repository, splitting them
+    /// where a shard would grow past the maximum size, and drops the shards
+    /// of the repository that are left over.
+    fn write_repository<'a>(
+        &mut self,
+        name: &str,
+        files: impl Iterator<Item = (&'a FileId, &'a search::File)>,
+        with_positions: bool,
+    ) -> io::Result<()> {
+        let mut paths = Vec::new();
+        let mut builder = ShardBuilder::new(with_positions);
+        let mut size = 0;
+        for (file_id, file) in files {
+            let file_size = file.content.len() as u64;
+            if size > 0 && size + file_size > self.max_shard_size {
+                let path = self.shard_path(name, paths.len());
+                std::mem::replace(&mut builder, ShardBuilder::new(with_positions)).write(&path)?;
+                paths.push(path);
+                size = 0;
+            }
+            builder.add(file_id, file);
+            size += file_size;
+        }
+        let path = self.shard_path(name, paths.len());
+        builder.write(&path)?;
+        paths.push(path);
+
+        let previous = self.shards.remove(name).map_or(0, |readers| readers.len());
+        for part in paths.len()..previous {
+            fs::remove_file(self.shard_path(name, part))?;
+        }
+        let readers = paths
+            .into_iter()
+            .map(ShardReader::open)
+            .collect::<io::Result<Vec<_>>>()?;
+        self.shards.insert(name.to_string(), readers);
+        Ok(())
+    }
+
+    fn shard_path(&self, name: &str, part: usize) -> PathBuf {
+        self.dir.join(format!("{}.{}.shard", escape(name), part))
+    }
+}
+
+/// Escapes the repository name so that it can be part of a file name.
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for b in name.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => escaped.push(b as char),
+            _ => escaped.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    escaped
+}
+
+/// Returns the repository name and the part of a shard file name.
+fn parse_shard_name(file_name: &str) -> Option<(String, usize)> {
+    let (escaped, part) = file_name.strip_suffix(".shard")?.split_once('.')?;
+    let mut name = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                name.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => name.push(b),
+        }
+    }
+    Some((String::from_utf8(name).ok()?, part.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sharded::*;
+    use crate::testutil::Fixture;
+    use crate::IndexOptions;
+
+    #[test]
+    fn test_shard_names() {
+        assert_eq!(escape("payments"), "payments");
+        assert_eq!(escape("github.com/org/repo"), "github%2Ecom%2Forg%2Frepo");
+        assert_eq!(
+            parse_shard_name("github%2Ecom%2Forg%2Frepo.3.shard"),
+            Some(("github.com/org/repo".to_string(), 3))
+        );
+        assert_eq!(parse_shard_name("payments.tmp"), None);
+        assert_eq!(parse_shard_name("payments.shard"), None);
+    }
+
+    #[test]
+    fn test_sharded_index() {
+        let fixture = Fixture::new();
+        for (repo, files) in [
+            ("payments", vec![("pay.go", "func pay(amount int) {}\n")]),
+            (
+                "billing",
+                vec![
+                    ("invoice.go", "func invoice(amount int) {}\n"),
+                    ("refund.go", "func refund(amount int) {}\n"),
+                    ("tax.go", "func tax() {}\n"),
+                ],
+            ),
+        ] {
+            fixture.write_files(&format!("src/{}", repo), &files);
+        }
+
+        let index_repository = |repo: &str| {
+            let mut index = Index::new(&fixture.path(&format!("index/{}", repo)));
+            index
+                .index_with_options(
+                    &fixture.path(&format!("src/{}", repo)),
+                    &IndexOptions::new().repository(repo),
+                )
+                .unwrap();
+            index
+        };
+        let shards = fixture.path("shards");
+        let mut sharded = ShardedIndex::open(&shards).unwrap();
+        // Every billing file is large enough to get a shard of its own.
+        sharded.set_max_shard_size(20);
+        sharded.add(&index_repository("payments")).unwrap();
+        sharded.add(&index_repository("billing")).unwrap();
+        assert_eq!(
+            sharded.repositories().collect::<Vec<_>>(),
+            vec!["billing", "payments"]
+        );
+        assert_eq!(sharded.shards["billing"].len(), 3);
+
+        let filenames = |sharded: &ShardedIndex, query: &str, options: &SearchOptions| {
+            let result = sharded.search_with_options(QueryNode::new(query), options);
+            let names: Vec<String> = result
+                .files
+                .iter()
+                .map(|m| m.filename.rsplit('/').next().unwrap().to_string())
+                .collect();
+            (names, result.truncated)
+        };
+        assert_eq!(
+            filenames(&sharded, "amount", &SearchOptions::new()),
+            (
+                vec!["invoice.go".into(), "refund.go".into(), "pay.go".into()],
+                false
+            )
+        );
+        assert_eq!(
+            filenames(&sharded, "amount", &SearchOptions::new().max_files(2)),
+            (vec!["invoice.go".into(), "refund.go".into()], true)
+        );
+
+        // Replacing a repository leaves the other one alone, and shards
+        // that are no longer needed are removed.
+        fs::remove_file(fixture.path("src/billing/refund.go")).unwrap();
+        fs::remove_file(fixture.path("src/billing/tax.go")).unwrap();
+        sharded.add(&index_repository("billing")).unwrap();
+        let sharded = ShardedIndex::open(&shards).unwrap();
+        assert_eq!(sharded.shards["billing"].len(), 1);
+        assert_eq!(
+            filenames(&sharded, "amount", &SearchOptions::new()).0,
+            vec!["invoice.go".to_string(), "pay.go".to_string()]
+        );
+
+        let mut sharded = sharded;
+        sharded.remove("payments").unwrap();
+        assert_eq!(sharded.repositories().collect::<Vec<_>>(), vec!["billing"]);
+        assert_eq!(fs::read_dir(&shards).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,22 @@
+use std::collections::BTreeMap;
+
+/// Stats summarizes what an index holds.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Stats {
+    /// Number of indexed files, not counting the documents of the history.
+    pub files: usize,
+    /// Bytes of content of the indexed files.
+    pub content_bytes: u64,
+    /// Number of files found while indexing but left out.
+    pub skipped_files: usize,
+    /// Number of files of every language, by canonical name.
+    pub languages: BTreeMap<String, usize>,
+    /// Number of files of every repository, by name.
+    pub repositories: BTreeMap<String, usize>,
+    /// Number of files of every branch, by name.
+    pub branches: BTreeMap<String, usize>,
+    /// Number of indexed commits.
+    pub commits: usize,
+    /// Number of indexed diffs, one per file changed by a commit.
+    pub diffs: usize,
+}
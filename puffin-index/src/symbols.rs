@@ -0,0 +1,281 @@
+use crate::lang::Language;
+use crate::search::symbol::Kind;
+use crate::search::Symbol;
+use regex::{Regex, RegexBuilder};
+use std::collections::BTreeMap;
+
+/// Rule finds definitions of one kind using a regex with a `name` group.
+struct Rule {
+    kind: Kind,
+    /// Functions defined on indented lines are methods of an enclosing type.
+    indent_is_method: bool,
+    re: Regex,
+}
+
+impl Rule {
+    fn new(kind: Kind, pattern: &str) -> Self {
+        Self {
+            kind,
+            indent_is_method: false,
+            re: RegexBuilder::new(pattern).multi_line(true).build().unwrap(),
+        }
+    }
+
+    /// Rule for functions that are methods when defined on an indented line.
+    fn function(pattern: &str) -> Self {
+        Self {
+            indent_is_method: true,
+            ..Self::new(Kind::Function, pattern)
+        }
+    }
+}
+
+/// Keywords that look like calls or definitions to the loose rules.
+const KEYWORDS: &[&str] = &[
+    "if", "else", "for", "foreach", "while", "switch", "case", "catch", "return", "new", "sizeof",
+    "function", "match", "throw", "delete", "typeof", "await", "do", "try", "using", "lock",
+];
+
+// Modifiers shared by the Java-like languages.
+const MODIFIERS: &str = r"(?:(?:public|private|protected|internal|static|final|abstract|sealed|partial|synchronized|native|override|virtual|async|default|open|data|inner|readonly|extern|unsafe|new)\s+)";
+
+lazy_static::lazy_static! {
+    static ref RULES: BTreeMap<&'static str, Vec<Rule>> = {
+        let mut rules = BTreeMap::new();
+        rules.insert("Rust", vec![
+            Rule::function(r#"^[ \t]*(?:pub(?:\([^)]*\))?\s+)?(?:(?:const|async|unsafe|extern(?:\s+"[^"]*")?)\s+)*fn\s+(?P<name>\w+)"#),
+            Rule::new(Kind::Type, r"^[ \t]*(?:pub(?:\([^)]*\))?\s+)?(?:struct|enum|union|trait|type)\s+(?P<name>\w+)"),
+            Rule::new(Kind::Constant, r"^[ \t]*(?:pub(?:\([^)]*\))?\s+)?(?:const|static)\s+(?:mut\s+)?(?P<name>\w+)\s*:"),
+            Rule::new(Kind::Function, r"^[ \t]*macro_rules!\s+(?P<name>\w+)"),
+        ]);
+        rules.insert("Go", vec![
+            Rule::new(Kind::Method, r"^func\s*\([^)]*\)\s*(?P<name>\w+)"),
+            Rule::new(Kind::Function, r"^func\s+(?P<name>\w+)"),
+            Rule::new(Kind::Type, r"^[ \t]*type\s+(?P<name>\w+)"),
+            Rule::new(Kind::Constant, r"^[ \t]*const\s+(?P<name>\w+)"),
+        ]);
+        rules.insert("Python", vec![
+            Rule::function(r"^[ \t]*(?:async\s+)?def\s+(?P<name>\w+)"),
+            Rule::new(Kind::Type, r"^[ \t]*class\s+(?P<name>\w+)"),
+            Rule::new(Kind::Constant, r"^(?P<name>[A-Z][A-Z0-9_]*)\s*(?::[^=\n]*)?="),
+        ]);
+        let script = || vec![
+            Rule::new(Kind::Function, r"^[ \t]*(?:export\s+)?(?:default\s+)?(?:async\s+)?function\s*\*?\s*(?P<name>[\w$]+)"),
+            Rule::new(Kind::Function, r"^[ \t]*(?:export\s+)?(?:const|let|var)\s+(?P<name>[\w$]+)\s*=\s*(?:async\s+)?(?:\([^)\n]*\)|[\w$]+)\s*(?::[^=\n]+)?=>"),
+            Rule::new(Kind::Type, r"^[ \t]*(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:class|interface|enum|type)\s+(?P<name>[\w$]+)"),
+            Rule::new(Kind::Constant, r"^[ \t]*(?:export\s+)?const\s+(?P<name>[A-Z][A-Z0-9_]*)\s*(?::[^=\n]+)?="),
+            Rule::new(Kind::Method, r"^[ \t]+(?:(?:public|private|protected|static|async|readonly|override|get|set)\s+)*(?P<name>[\w$]+)\s*\([^)\n]*\)\s*(?::\s*[^{\n]+)?\{\s*$"),
+        ];
+        rules.insert("JavaScript", script());
+        rules.insert("TypeScript", script());
+        let java = || vec![
+            Rule::new(Kind::Type, &format!(r"^[ \t]*{}*(?:class|interface|enum|record|struct|object|@interface)\s+(?P<name>\w+)", MODIFIERS)),
+            Rule::new(Kind::Constant, &format!(r"^[ \t]*{}*(?:static\s+final|const)\s+[\w<>\[\],.?]+(?:\s*<[^>\n]*>)?\s+(?P<name>\w+)\s*=", MODIFIERS)),
+            Rule::new(Kind::Method, &format!(r"^[ \t]+{}+[\w<>\[\],.?]+(?:\s*<[^>\n]*>)?\s+(?P<name>\w+)\s*\(", MODIFIERS)),
+        ];
+        rules.insert("Java", java());
+        rules.insert("C#", java());
+        rules.insert("Kotlin", vec![
+            Rule::function(&format!(r"^[ \t]*(?:{}|(?:suspend|inline|operator|infix|tailrec)\s+)*fun\s+(?:<[^>\n]*>\s*)?(?:[\w.]+\.)?(?P<name>\w+)", MODIFIERS)),
+            Rule::new(Kind::Type, &format!(r"^[ \t]*(?:{}|(?:enum|annotation|value)\s+)*(?:class|interface|object)\s+(?P<name>\w+)", MODIFIERS)),
+            Rule::new(Kind::Constant, r"^[ \t]*(?:\w+\s+)*const\s+val\s+(?P<name>\w+)"),
+        ]);
+        let c = || vec![
+            Rule::new(Kind::Function, r"^(?:[\w:*&<>,]+\s+)+[*&]*(?P<name>[A-Za-z_]\w*(?:::~?\w+)*)\s*\([^;]*$"),
+            Rule::new(Kind::Type, r"^[ \t]*(?:typedef\s+)?(?:struct|class|union|enum(?:\s+class)?)\s+(?P<name>\w+)\s*(?:[:{]|$)"),
+            Rule::new(Kind::Type, r"^[ \t]*typedef\s+[^;(]*\b(?P<name>\w+)\s*;"),
+            Rule::new(Kind::Constant, r"^[ \t]*#\s*define\s+(?P<name>\w+)(?:\s|$)"),
+            Rule::new(Kind::Function, r"^[ \t]*#\s*define\s+(?P<name>\w+)\("),
+        ];
+        rules.insert("C", c());
+        rules.insert("C++", c());
+        rules.insert("Ruby", vec![
+            Rule::function(r"^[ \t]*def\s+(?:self\.)?(?P<name>[\w?!=]+)"),
+            Rule::new(Kind::Type, r"^[ \t]*(?:class|module)\s+(?P<name>[\w:]+)"),
+            Rule::new(Kind::Constant, r"^[ \t]*(?P<name>[A-Z][A-Z0-9_]*)\s*="),
+        ]);
+        rules.insert("PHP", vec![
+            Rule::function(r"^[ \t]*(?:(?:public|private|protected|static|abstract|final)\s+)*function\s+&?(?P<name>\w+)"),
+            Rule::new(Kind::Type, r"^[ \t]*(?:(?:abstract|final|readonly)\s+)*(?:class|interface|trait|enum)\s+(?P<name>\w+)"),
+            Rule::new(Kind::Constant, r"^[ \t]*(?:(?:public|private|protected|final)\s+)*const\s+(?P<name>\w+)\s*="),
+        ]);
+        rules
+    };
+}
+
+/// Finds definitions of functions, methods, types and constants in the
+/// content, ordered by their position. Languages without rules have none.
+pub(crate) fn extract(language: Option<&Language>, content: &str) -> Vec<Symbol> {
+    let Some(rules) = language.and_then(|l| RULES.get(l.name)) else {
+        return Vec::new();
+    };
+
+    // Earlier rules win when more of them find a name at the same position.
+    let mut symbols: BTreeMap<usize, Symbol> = BTreeMap::new();
+    for rule in rules {
+        for captures in rule.re.captures_iter(content) {
+            let name = captures.name("name").unwrap();
+            if KEYWORDS.contains(&name.as_str()) || symbols.contains_key(&name.start()) {
+                continue;
+            }
+            let line = captures.get(0).unwrap().as_str();
+            let kind = match rule.kind {
+                Kind::Function if rule.indent_is_method && line.starts_with([' ', '\t']) => {
+                    Kind::Method
+                }
+                kind => kind,
+            };
+            symbols.insert(
+                name.start(),
+                Symbol {
+                    name: name.as_str().to_string(),
+                    kind: kind.into(),
+                    start: name.start() as u64,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    // Line numbers are counted once for all symbols, in order.
+    let mut line_number = 1;
+    let mut counted = 0;
+    symbols
+        .into_values()
+        .map(|mut symbol| {
+            let start = symbol.start as usize;
+            line_number += content[counted..start].matches('\n').count();
+            counted = start;
+            symbol.line_number = line_number as u32;
+            symbol
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang;
+    use crate::symbols::*;
+
+    fn symbols(language: &str, content: &str) -> Vec<(String, Kind, u32)> {
+        extract(lang::find(language), content)
+            .into_iter()
+            .map(|s| {
+                assert_eq!(&content[s.start as usize..][..s.name.len()], s.name);
+                (s.name.clone(), s.kind(), s.line_number)
+            })
+            .collect()
+    }
+
+    fn expected(symbols: &[(&str, Kind, u32)]) -> Vec<(String, Kind, u32)> {
+        symbols
+            .iter()
+            .map(|(name, kind, line)| (name.to_string(), *kind, *line))
+            .collect()
+    }
+
+    #[test]
+    fn test_rust() {
+        let content = "\
+pub const LIMIT: usize = 10;
+
+pub struct Payment {}
+
+impl Payment {
+    pub async fn process(&self) {}
+
+    fn refund(&self) {}
+}
+
+fn process_payment() {
+    if ready() {}
+}
+";
+        assert_eq!(
+            symbols("rust", content),
+            expected(&[
+                ("LIMIT", Kind::Constant, 1),
+                ("Payment", Kind::Type, 3),
+                ("process", Kind::Method, 6),
+                ("refund", Kind::Method, 8),
+                ("process_payment", Kind::Function, 11),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_go() {
+        let content = "\
+package payments
+
+const Limit = 10
+
+type Processor struct{}
+
+func (p *Processor) ProcessPayment() error {
+\treturn nil
+}
+
+func NewProcessor() *Processor {}
+";
+        assert_eq!(
+            symbols("go", content),
+            expected(&[
+                ("Limit", Kind::Constant, 3),
+                ("Processor", Kind::Type, 5),
+                ("ProcessPayment", Kind::Method, 7),
+                ("NewProcessor", Kind::Function, 11),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_other_languages() {
+        assert_eq!(
+            symbols(
+                "python",
+                "MAX_RETRIES = 3\n\nclass Payment:\n    def process(self):\n        pass\n\n    def refund(self):\n        pass\n"
+            ),
+            expected(&[
+                ("MAX_RETRIES", Kind::Constant, 1),
+                ("Payment", Kind::Type, 3),
+                ("process", Kind::Method, 4),
+                ("refund", Kind::Method, 7),
+            ])
+        );
+        assert_eq!(
+            symbols(
+                "typescript",
+                "export class Payment {\n  async process(id: string): Promise<void> {\n    if (id) {\n    }\n  }\n}\nexport const toCents = (x: number) => x * 100;\n"
+            ),
+            expected(&[
+                ("Payment", Kind::Type, 1),
+                ("process", Kind::Method, 2),
+                ("toCents", Kind::Function, 7),
+            ])
+        );
+        assert_eq!(
+            symbols(
+                "java",
+                "public class Payment {\n    private static final int LIMIT = 10;\n    public void process(String id) {\n    }\n}\n"
+            ),
+            expected(&[
+                ("Payment", Kind::Type, 1),
+                ("LIMIT", Kind::Constant, 2),
+                ("process", Kind::Method, 3),
+            ])
+        );
+        assert_eq!(
+            symbols(
+                "c",
+                "#define LIMIT 10\n\nstruct payment {\n};\n\nstatic int process_payment(struct payment *p)\n{\n    return check(p);\n}\n"
+            ),
+            expected(&[
+                ("LIMIT", Kind::Constant, 1),
+                ("payment", Kind::Type, 3),
+                ("process_payment", Kind::Function, 6),
+            ])
+        );
+        assert!(symbols("markdown", "# fn main() {}\n").is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+/// Fixture is a temporary directory a test writes its sources and indexes
+/// into. It is removed once the fixture is dropped, even if the test fails.
+pub(crate) struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        // Hidden directories aren't indexed, so the name mustn't start with a dot.
+        let dir = tempfile::Builder::new()
+            .prefix("puffin-test-")
+            .tempdir()
+            .unwrap();
+        Self { dir }
+    }
+
+    /// Returns the path of `name` within the directory.
+    pub fn path(&self, name: &str) -> String {
+        self.dir.path().join(name).to_str().unwrap().to_string()
+    }
+
+    /// Writes the file at `name` within the directory, creating its parent
+    /// directories, and returns its path.
+    pub fn write(&self, name: &str, content: impl AsRef<[u8]>) -> String {
+        let path = self.path(name);
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Writes the files into the `dir` directory and returns its path.
+    pub fn write_files(&self, dir: &str, files: &[(&str, &str)]) -> String {
+        let path = self.path(dir);
+        fs::create_dir_all(&path).unwrap();
+        for (name, content) in files {
+            self.write(&format!("{}/{}", dir, name), content);
+        }
+        path
+    }
+}
+
+/// Whether the git binary can be run. Tests indexing git repositories are
+/// skipped without it.
+pub(crate) fn has_git() -> bool {
+    let found = Command::new("git")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if !found {
+        eprintln!("git not found, skipping the test");
+    }
+    found
+}
+
+/// Runs the git command in the directory, as a test user.
+pub(crate) fn git(dir: &str, args: &[&str]) {
+    let status = Command::new("git")
+        .args([
+            "-C",
+            dir,
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+        ])
+        .args(args)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
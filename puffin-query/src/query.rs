@@ -4,6 +4,7 @@ use pest::{
     Parser,
 };
 use pest_derive::Parser;
+use std::fmt;
 
 #[derive(Parser)]
 #[grammar = "query.pest"]
@@ -21,7 +22,18 @@ lazy_static::lazy_static! {
     };
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Type of the documents matched by a `type:` atom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocumentType {
+    /// Files of a directory or a branch.
+    File,
+    /// Messages of the indexed commits.
+    Commit,
+    /// Lines added or removed by the indexed commits.
+    Diff,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum QueryNode {
     Or {
         lhs: Box<QueryNode>,
@@ -34,24 +46,118 @@ pub enum QueryNode {
     Not(Box<QueryNode>),
     Lang(String),
     File(String),
+    FileRegex(String),
+    /// Files of the repositories named like the text.
+    Repo(String),
+    RepoRegex(String),
+    /// Files of the branches named like the text.
+    Branch(String),
+    BranchRegex(String),
+    /// Definitions named like the text.
+    Symbol(String),
+    SymbolRegex(String),
+    /// Documents of the given type.
+    Type(DocumentType),
+    /// Commits and diffs of the authors named like the text.
+    Author(String),
+    AuthorRegex(String),
+    /// Commits and diffs authored before the start of the day, given as
+    /// `YYYY-MM-DD` in UTC.
+    Before(String),
+    /// Commits and diffs authored on the day or after it.
+    After(String),
     Term(String),
     Regex(String),
+    /// Query with case sensitivity set by a `case:` atom.
+    Case {
+        sensitive: bool,
+        query: Box<QueryNode>,
+    },
+}
+
+/// ParseError describes why a query couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
+impl std::error::Error for ParseError {}
+
 impl QueryNode {
+    /// Parses the query, panicking if it is invalid.
     pub fn new(s: &str) -> Self {
-        let mut pairs = QueryParser::parse(Rule::query, s).unwrap();
-        let query = pairs.next().unwrap().into_inner();
+        Self::parse(s).unwrap_or_else(|err| panic!("invalid query {:?}: {}", s, err))
+    }
+
+    /// Parses the query, or describes where it is invalid.
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let pairs =
+            QueryParser::parse(Rule::query, s).map_err(|err| ParseError(err.to_string()))?;
+        let mut query = None;
+        let mut sensitive = None;
+        for pair in pairs {
+            match pair.as_rule() {
+                Rule::expr => query = Some(pair.into_inner()),
+                Rule::case => sensitive = Some(pair.into_inner().as_str() == "yes"),
+                _ => {}
+            }
+        }
+        let query = query.unwrap();
 
         fn parse_value(primary: Pair<Rule>) -> QueryNode {
             match primary.as_rule() {
                 Rule::query => parse_value(primary.into_inner().next().unwrap()),
                 Rule::atom => parse_value(primary.into_inner().next().unwrap()),
-                Rule::file => QueryNode::File(primary.as_str().into()),
-                Rule::lang => QueryNode::Lang(primary.as_str().into()),
+                Rule::file => {
+                    let text = primary.into_inner().next().unwrap();
+                    match text.as_rule() {
+                        Rule::regex => QueryNode::FileRegex(parse_text(text)),
+                        _ => QueryNode::File(parse_text(text)),
+                    }
+                }
+                Rule::repo => {
+                    let text = primary.into_inner().next().unwrap();
+                    match text.as_rule() {
+                        Rule::regex => QueryNode::RepoRegex(parse_text(text)),
+                        _ => QueryNode::Repo(parse_text(text)),
+                    }
+                }
+                Rule::branch => {
+                    let text = primary.into_inner().next().unwrap();
+                    match text.as_rule() {
+                        Rule::regex => QueryNode::BranchRegex(parse_text(text)),
+                        _ => QueryNode::Branch(parse_text(text)),
+                    }
+                }
+                Rule::sym => {
+                    let text = primary.into_inner().next().unwrap();
+                    match text.as_rule() {
+                        Rule::regex => QueryNode::SymbolRegex(parse_text(text)),
+                        _ => QueryNode::Symbol(parse_text(text)),
+                    }
+                }
+                Rule::author => {
+                    let text = primary.into_inner().next().unwrap();
+                    match text.as_rule() {
+                        Rule::regex => QueryNode::AuthorRegex(parse_text(text)),
+                        _ => QueryNode::Author(parse_text(text)),
+                    }
+                }
+                Rule::doc_type => QueryNode::Type(match primary.into_inner().as_str() {
+                    "commit" => DocumentType::Commit,
+                    "diff" => DocumentType::Diff,
+                    _ => DocumentType::File,
+                }),
+                Rule::before => QueryNode::Before(primary.into_inner().as_str().into()),
+                Rule::after => QueryNode::After(primary.into_inner().as_str().into()),
+                Rule::lang => QueryNode::Lang(parse_text(primary.into_inner().next().unwrap())),
                 Rule::query_text => todo!(),
                 Rule::term => QueryNode::Term(primary.as_str().into()),
-                Rule::regex => QueryNode::Regex(primary.into_inner().as_str().into()),
+                Rule::regex => QueryNode::Regex(primary.into_inner().as_str().replace("\\/", "/")),
                 Rule::exact => QueryNode::Term(primary.into_inner().as_str().into()),
                 Rule::expr => parse_expr(primary.into_inner()),
                 _ => {
@@ -60,27 +166,26 @@ impl QueryNode {
             }
         }
 
+        fn parse_text(primary: Pair<Rule>) -> String {
+            match primary.as_rule() {
+                Rule::exact | Rule::regex => primary.into_inner().as_str().into(),
+                _ => primary.as_str().into(),
+            }
+        }
+
         fn parse_expr(primary: Pairs<Rule>) -> QueryNode {
             PRATT_PARSER
                 .map_primary(parse_value)
-                .map_infix(|lhs, op, rhs| {
-                    println!("Lhs:   {:?}", lhs);
-                    println!("Op:    {:?}", op);
-                    println!("Rhs:   {:?}", rhs);
-
-                    match op.as_rule() {
-                        Rule::and => QueryNode::And {
-                            lhs: Box::new(lhs),
-                            rhs: Box::new(rhs),
-                        },
-                        Rule::or => QueryNode::Or {
-                            lhs: Box::new(lhs),
-                            rhs: Box::new(rhs),
-                        },
-                        rule => {
-                            unreachable!("Expr::parse expected infix operation, found {:?}", rule)
-                        }
-                    }
+                .map_infix(|lhs, op, rhs| match op.as_rule() {
+                    Rule::and => QueryNode::And {
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                    Rule::or => QueryNode::Or {
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                    rule => unreachable!("Expr::parse expected infix operation, found {:?}", rule),
                 })
                 .map_prefix(|op, rhs| match op.as_rule() {
                     Rule::not => QueryNode::Not(Box::new(rhs)),
@@ -89,7 +194,58 @@ impl QueryNode {
                 .parse(primary)
         }
 
-        parse_expr(query)
+        Ok(match sensitive {
+            Some(sensitive) => QueryNode::Case {
+                sensitive,
+                query: Box::new(parse_expr(query)),
+            },
+            None => parse_expr(query),
+        })
+    }
+
+    /// Whether the query is case sensitive. Unless set by a `case:` atom, it
+    /// is smart-case: case sensitive only if the query has an uppercase letter.
+    pub fn is_case_sensitive(&self) -> bool {
+        match self {
+            QueryNode::Case { sensitive, .. } => *sensitive,
+            _ => self.has_uppercase(),
+        }
+    }
+
+    fn has_uppercase(&self) -> bool {
+        match self {
+            QueryNode::Or { lhs, rhs } | QueryNode::And { lhs, rhs } => {
+                lhs.has_uppercase() || rhs.has_uppercase()
+            }
+            QueryNode::Not(q) => q.has_uppercase(),
+            QueryNode::Case { query, .. } => query.has_uppercase(),
+            QueryNode::File(t)
+            | QueryNode::Repo(t)
+            | QueryNode::Branch(t)
+            | QueryNode::Symbol(t)
+            | QueryNode::Author(t)
+            | QueryNode::Term(t) => t.chars().any(char::is_uppercase),
+            // Escapes such as `\W` or `\p{Lu}` aren't uppercase text.
+            QueryNode::FileRegex(r)
+            | QueryNode::RepoRegex(r)
+            | QueryNode::BranchRegex(r)
+            | QueryNode::SymbolRegex(r)
+            | QueryNode::AuthorRegex(r)
+            | QueryNode::Regex(r) => {
+                let mut escaped = false;
+                let mut class = false;
+                r.chars().any(|c| {
+                    let uppercase = !escaped && !class && c.is_uppercase();
+                    class = (class || (escaped && matches!(c, 'p' | 'P'))) && c != '}';
+                    escaped = !escaped && c == '\\';
+                    uppercase
+                })
+            }
+            QueryNode::Lang(_)
+            | QueryNode::Type(_)
+            | QueryNode::Before(_)
+            | QueryNode::After(_) => false,
+        }
     }
 }
 
@@ -116,6 +272,98 @@ mod tests {
 
         assert_eq!(QueryNode::new("/re*/"), QueryNode::Regex("re*".into()));
 
+        assert_eq!(
+            QueryNode::new("/fn\\s+\\w+\\/\\d/"),
+            QueryNode::Regex("fn\\s+\\w+/\\d".into())
+        );
+
+        assert_eq!(
+            QueryNode::new("max_file_size"),
+            QueryNode::Term("max_file_size".into())
+        );
+        assert_eq!(
+            QueryNode::new("_private AND lang:go"),
+            QueryNode::And {
+                lhs: Box::new(QueryNode::Term("_private".into())),
+                rhs: Box::new(QueryNode::Lang("go".into())),
+            }
+        );
+
+        assert_eq!(QueryNode::new("lang:go"), QueryNode::Lang("go".into()));
+
+        assert_eq!(
+            QueryNode::new("file:handler"),
+            QueryNode::File("handler".into())
+        );
+
+        assert_eq!(
+            QueryNode::new("file:/_test\\.go$/"),
+            QueryNode::FileRegex("_test\\.go$".into())
+        );
+
+        assert_eq!(
+            QueryNode::new("repo:payments AND NOT repo:/^legacy-/"),
+            QueryNode::And {
+                lhs: Box::new(QueryNode::Repo("payments".into())),
+                rhs: Box::new(QueryNode::Not(Box::new(QueryNode::RepoRegex(
+                    "^legacy-".into()
+                ))))
+            }
+        );
+
+        assert_eq!(
+            QueryNode::new("branch:main OR branch:/^release-/"),
+            QueryNode::Or {
+                lhs: Box::new(QueryNode::Branch("main".into())),
+                rhs: Box::new(QueryNode::BranchRegex("^release-".into()))
+            }
+        );
+
+        assert_eq!(
+            QueryNode::new("sym:ProcessPayment"),
+            QueryNode::Symbol("ProcessPayment".into())
+        );
+
+        assert_eq!(
+            QueryNode::new("sym:/^New\\w+/ AND lang:go"),
+            QueryNode::And {
+                lhs: Box::new(QueryNode::SymbolRegex("^New\\w+".into())),
+                rhs: Box::new(QueryNode::Lang("go".into()))
+            }
+        );
+
+        assert_eq!(
+            QueryNode::new("type:diff AND author:alice AND after:2024-01-31"),
+            QueryNode::And {
+                lhs: Box::new(QueryNode::And {
+                    lhs: Box::new(QueryNode::Type(DocumentType::Diff)),
+                    rhs: Box::new(QueryNode::Author("alice".into()))
+                }),
+                rhs: Box::new(QueryNode::After("2024-01-31".into()))
+            }
+        );
+
+        assert_eq!(
+            QueryNode::new("type:commit AND NOT author:/^bot/ AND before:2023-06-01"),
+            QueryNode::And {
+                lhs: Box::new(QueryNode::And {
+                    lhs: Box::new(QueryNode::Type(DocumentType::Commit)),
+                    rhs: Box::new(QueryNode::Not(Box::new(QueryNode::AuthorRegex(
+                        "^bot".into()
+                    ))))
+                }),
+                rhs: Box::new(QueryNode::Before("2023-06-01".into()))
+            }
+        );
+
+        assert_eq!(
+            QueryNode::new("merchant AND NOT lang:\"C++\""),
+            QueryNode::And {
+                lhs: Box::new(QueryNode::Term("merchant".into())),
+                rhs: Box::new(QueryNode::Not(Box::new(QueryNode::Lang("C++".into()))))
+            }
+        );
+
         assert_eq!(
             QueryNode::new("(Foo AND Bar) OR (Baz AND Buz)"),
             QueryNode::Or {
@@ -129,5 +377,45 @@ mod tests {
                 }),
             }
         );
+
+        assert_eq!(
+            QueryNode::new("case:yes merchant"),
+            QueryNode::Case {
+                sensitive: true,
+                query: Box::new(QueryNode::Term("merchant".into()))
+            }
+        );
+
+        assert_eq!(
+            QueryNode::new("Merchant OR Business case:no"),
+            QueryNode::Case {
+                sensitive: false,
+                query: Box::new(QueryNode::Or {
+                    lhs: Box::new(QueryNode::Term("Merchant".into())),
+                    rhs: Box::new(QueryNode::Term("Business".into()))
+                })
+            }
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(QueryNode::parse("merchant AND").is_err());
+        assert!(QueryNode::parse("(merchant").is_err());
+        assert!(QueryNode::parse("before:yesterday").is_err());
+        assert!(QueryNode::parse("merchant").is_ok());
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(!QueryNode::new("merchant").is_case_sensitive());
+        assert!(QueryNode::new("Merchant").is_case_sensitive());
+        assert!(QueryNode::new("lang:Go AND file:Handler").is_case_sensitive());
+        assert!(!QueryNode::new("lang:Go AND merchant").is_case_sensitive());
+        assert!(!QueryNode::new("/\\w+\\Wfoo\\p{Lu}/").is_case_sensitive());
+        assert!(QueryNode::new("/\\w+Foo/").is_case_sensitive());
+        assert!(QueryNode::new("sym:Payment").is_case_sensitive());
+        assert!(QueryNode::new("case:yes merchant").is_case_sensitive());
+        assert!(!QueryNode::new("case:no Merchant").is_case_sensitive());
     }
 }
@@ -1,73 +1,539 @@
-use puffin_index::Index;
+use puffin_index::{DocumentKind, FileMatch, Index, IndexOptions, SearchOptions, SearchResult};
 use puffin_query::QueryNode;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::ops::Range;
+use std::process;
+use std::time::Duration;
 
-fn index_and_search() {
-    let dir_path = "../../sumup/merchants";
+const USAGE: &str = "\
+usage:
+    puffin index <dir> --out <index> [options]
+    puffin search <index> <query> [options]
+    puffin stats <index>
+    puffin list-files <index> [<fragment>]
 
-    let mut index = Index::new("disk");
-    log::info!("indexing");
-    index.index(dir_path);
-    log::info!("indexing done");
+index options:
+    --branch <rev>            index the branch from the git objects instead of
+                              the working tree, can be given more than once
+    --history <rev>           also index the commit history leading to <rev>
+    --positions               record trigram positions when creating the index
+    --max-file-size <bytes>   skip larger files
+    --max-files <count>       index at most this many files
+    --hidden                  index hidden files
+    --follow-links            follow symbolic links
+    --ignore <glob>           skip files matching the .gitignore line
+    --include <glob>          only index files matching the glob
+    --exclude <glob>          never index files matching the glob
+    --threads <count>         number of indexing threads
+    --repo <name>             name of the repository
+    --url-template <url>      URL of the files, with {path} and {line}
 
-    log::info!("flushing");
-    index.flush().expect("flush index");
-    log::info!("flushing done");
+search options:
+    --max-files <count>       return at most this many files
+    --max-matches <count>     stop after this many matches
+    --max-line-matches <count>
+                              return at most this many lines of every file
+    --timeout <ms>            stop searching after this many milliseconds
+    -C, --context <lines>     print lines around every matching line
+    --format <format>         text, json or files
 
-    log::info!("searching");
-    let result = index.search(QueryNode::new("merchant"));
-    log::info!("searching done");
+The exit status is 0 if anything matched, 1 if nothing did, and 2 on errors.";
 
-    for f in result {
-        println!("match in: {:?}", f.filename);
+/// Escape sequences around highlights when printing to a terminal.
+const HIGHLIGHT_START: &str = "\x1b[1;31m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// Output formats of the search command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    /// Matching lines under the name of their file, like ripgrep prints them.
+    Text,
+    /// A single JSON document holding all of the results.
+    Json,
+    /// Names of the matching files, one per line.
+    Files,
+}
+
+struct IndexArgs {
+    dir: String,
+    out: String,
+    options: IndexOptions,
+    branches: Vec<String>,
+    history: Option<String>,
+    positions: bool,
+}
+
+struct SearchArgs {
+    index: String,
+    query: String,
+    options: SearchOptions,
+    format: Format,
+}
+
+/// Parses `<dir> --out <index> [options]` of the index command.
+fn parse_index_args(args: &[String]) -> Result<IndexArgs, String> {
+    let mut dir = None;
+    let mut out = None;
+    let mut branches = Vec::new();
+    let mut history = None;
+    let mut positions = false;
+    let mut options = IndexOptions::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value of {}", arg))
+        };
+        let number = |value: &String| {
+            value
+                .parse()
+                .map_err(|err| format!("invalid value of {}: {}", arg, err))
+        };
+        options = match arg.as_str() {
+            "--out" => {
+                out = Some(value()?.clone());
+                options
+            }
+            "--branch" => {
+                branches.push(value()?.clone());
+                options
+            }
+            "--history" => {
+                history = Some(value()?.clone());
+                options
+            }
+            "--positions" => {
+                positions = true;
+                options
+            }
+            "--max-file-size" => options.max_file_size(number(value()?)?),
+            "--max-files" => options.max_file_count(number(value()?)? as usize),
+            "--hidden" => options.hidden(true),
+            "--follow-links" => options.follow_links(true),
+            "--ignore" => options.ignore(value()?),
+            "--include" => options.include(value()?),
+            "--exclude" => options.exclude(value()?),
+            "--threads" => options.threads(number(value()?)? as usize),
+            "--repo" => options.repository(value()?),
+            "--url-template" => options.url_template(value()?),
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            _ if dir.is_none() => {
+                dir = Some(arg.clone());
+                options
+            }
+            _ => return Err(format!("unexpected argument {}", arg)),
+        };
     }
+    Ok(IndexArgs {
+        dir: dir.ok_or("missing directory to index")?,
+        out: out.ok_or("missing --out <index>")?,
+        options,
+        branches,
+        history,
+        positions,
+    })
 }
 
-fn search_only() {
-    let dir_path = "../../sumup/merchants";
+/// Parses `<index> <query> [options]` of the search command.
+fn parse_search_args(args: &[String]) -> Result<SearchArgs, String> {
+    let mut positional = Vec::new();
+    let mut format = Format::Text;
+    let mut options = SearchOptions::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value of {}", arg))
+        };
+        let number = |value: &String| {
+            value
+                .parse()
+                .map_err(|err| format!("invalid value of {}: {}", arg, err))
+        };
+        options = match arg.as_str() {
+            "--max-files" => options.max_files(number(value()?)?),
+            "--max-matches" => options.max_matches(number(value()?)?),
+            "--max-line-matches" => options.max_line_matches(number(value()?)?),
+            "--timeout" => options.time_budget(Duration::from_millis(number(value()?)? as u64)),
+            "-C" | "--context" => options.context_lines(number(value()?)?),
+            "--format" => {
+                format = match value()?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "files" => Format::Files,
+                    other => return Err(format!("unknown format {}", other)),
+                };
+                options
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            _ => {
+                positional.push(arg.clone());
+                options
+            }
+        };
+    }
+    let [index, query]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| "expected an index and a query".to_string())?;
+    Ok(SearchArgs {
+        index,
+        query,
+        options,
+        format,
+    })
+}
 
-    let mut index = Index::new("disk");
-    log::info!("indexing");
-    index.index(dir_path);
-    log::info!("indexing done");
+fn open_index(loc: &str) -> Result<Index, String> {
+    Index::open(loc).map_err(|err| format!("unable to open index {}: {}", loc, err))
+}
 
-    // log::info!("searching");
-    // let result = index.search(QueryNode::new("lang:go"));
-    // for f in result {
-    //     println!("match in: {:?}", f.filename);
-    // }
-    // log::info!("searching done");
+fn index(args: &IndexArgs) -> Result<(), String> {
+    // Indexing into an existing index only updates what changed.
+    let mut index = match Index::open(&args.out) {
+        Ok(index) => index,
+        Err(err) if err.kind() == io::ErrorKind::NotFound && args.positions => {
+            Index::with_positions(&args.out)
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Index::new(&args.out),
+        Err(err) => return Err(format!("unable to open index {}: {}", args.out, err)),
+    };
 
-    log::info!("searching");
-    let result = index.search(QueryNode::new("merchant"));
-    for f in result {
-        println!("match in: {:?}", f.filename);
+    let failed = |err: io::Error| format!("unable to index {}: {}", args.dir, err);
+    if args.branches.is_empty() {
+        index
+            .index_with_options(&args.dir, &args.options)
+            .map_err(failed)?;
     }
-    log::info!("searching done");
+    for branch in args.branches.iter() {
+        index
+            .index_branch(&args.dir, branch, &args.options)
+            .map_err(failed)?;
+    }
+    if let Some(revision) = args.history.as_ref() {
+        index
+            .index_history(&args.dir, revision, &args.options)
+            .map_err(failed)?;
+    }
+    index
+        .flush()
+        .map_err(|err| format!("unable to write index {}: {}", args.out, err))
+}
 
-    log::info!("searching");
-    let result = index.search(QueryNode::new("merchant AND business"));
-    for f in result {
-        println!("match in: {:?}", f.filename);
+/// Runs the search, returning whether anything matched.
+fn search(args: &SearchArgs) -> Result<bool, String> {
+    let query = QueryNode::parse(&args.query).map_err(|err| format!("invalid query: {}", err))?;
+    let index = open_index(&args.index)?;
+    let result = index.search_with_options(query, &args.options);
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let printed = match args.format {
+        Format::Text => print_text(&mut out, &result, io::stdout().is_terminal()),
+        Format::Json => print_json(&mut out, &result),
+        Format::Files => print_files(&mut out, &result),
+    };
+    finish(printed.and_then(|_| out.flush()))?;
+    if result.truncated && args.format != Format::Json {
+        eprintln!("results were truncated by the limits");
+    }
+    Ok(!result.files.is_empty())
+}
+
+/// Treats a closed pipe, such as the one of `puffin search ... | head`, as
+/// the reader having seen enough.
+fn finish(printed: io::Result<()>) -> Result<(), String> {
+    match printed {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+            Err(format!("unable to print results: {}", err))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Prints the results like grep does: matching lines are separated from their
+/// number by `:`, context lines by `-`. Every file starts with a heading.
+fn print_text(out: &mut impl Write, result: &SearchResult, color: bool) -> io::Result<()> {
+    for (i, f) in result.files.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}", heading(f))?;
+        for s in f.symbols.iter() {
+            writeln!(out, "{}:{} {:?}", s.line_number, s.name, s.kind)?;
+        }
+
+        // Context lines shared by nearby matches are printed once.
+        let matched: BTreeSet<usize> = f.line_matches.iter().map(|l| l.line_number).collect();
+        let mut printed = 0;
+        for l in f.line_matches.iter() {
+            let context_before = (l.line_number - l.before.len()..).zip(l.before.iter());
+            let context_after = (l.line_number + 1..).zip(l.after.iter());
+            for (line_number, line) in context_before {
+                if line_number > printed && !matched.contains(&line_number) {
+                    writeln!(out, "{}-{}", line_number, line)?;
+                }
+            }
+            writeln!(
+                out,
+                "{}:{}",
+                l.line_number,
+                highlight(&l.line, &l.highlights, color)
+            )?;
+            printed = l.line_number;
+            for (line_number, line) in context_after {
+                if matched.contains(&line_number) {
+                    break;
+                }
+                writeln!(out, "{}-{}", line_number, line)?;
+                printed = line_number;
+            }
+        }
     }
-    log::info!("searching done");
+    Ok(())
+}
 
-    log::info!("searching");
-    let result = index.search(QueryNode::new("profile OR business"));
-    for f in result {
-        println!("match in: {:?}", f.filename);
+/// Describes the file, or the commit for documents of the history.
+fn heading(f: &FileMatch) -> String {
+    let mut heading = match (f.kind, f.commit.as_ref()) {
+        (DocumentKind::Commit, Some(c)) => {
+            format!("commit {} {} {}", c.id, c.author, format_date(c.time))
+        }
+        (DocumentKind::Diff, Some(c)) => format!(
+            "{} (commit {} {})",
+            f.filename,
+            &c.id[..c.id.len().min(12)],
+            format_date(c.time)
+        ),
+        _ => f.filename.clone(),
+    };
+    if !f.branches.is_empty() {
+        let names: Vec<&str> = f.branches.iter().map(|b| b.name.as_str()).collect();
+        heading.push_str(&format!(" [{}]", names.join(", ")));
     }
-    log::info!("searching done");
+    if let Some(url) = f.url.as_ref() {
+        heading.push_str(&format!(" ({})", url));
+    }
+    heading
+}
 
-    log::info!("searching");
-    let result = index.search(QueryNode::new("NOT merchant"));
-    for f in result {
-        println!("match in: {:?}", f.filename);
+/// Wraps the highlighted ranges of the line in escape sequences if `color`.
+fn highlight(line: &str, highlights: &[Range<usize>], color: bool) -> String {
+    if !color {
+        return line.to_string();
+    }
+    let mut result = String::with_capacity(line.len());
+    let mut end = 0;
+    for range in highlights {
+        // Matches may end on the line break trimmed off the line. Ranges
+        // overlapping the previous one or splitting a character are left out.
+        let range = range.start.min(line.len())..range.end.min(line.len());
+        if range.start < end
+            || !line.is_char_boundary(range.start)
+            || !line.is_char_boundary(range.end)
+        {
+            continue;
+        }
+        result.push_str(&line[end..range.start]);
+        result.push_str(HIGHLIGHT_START);
+        result.push_str(&line[range.clone()]);
+        result.push_str(HIGHLIGHT_END);
+        end = range.end;
+    }
+    result.push_str(&line[end..]);
+    result
+}
+
+fn print_json(out: &mut impl Write, result: &SearchResult) -> io::Result<()> {
+    let files: Vec<_> = result.files.iter().map(file_json).collect();
+    let result = json!({
+        "files": files,
+        "truncated": result.truncated,
+    });
+    serde_json::to_writer_pretty(&mut *out, &result)?;
+    writeln!(out)
+}
+
+fn file_json(f: &FileMatch) -> serde_json::Value {
+    let branches: Vec<_> = f
+        .branches
+        .iter()
+        .map(|b| json!({ "name": b.name, "commit": b.commit }))
+        .collect();
+    let symbols: Vec<_> = f
+        .symbols
+        .iter()
+        .map(|s| {
+            json!({
+                "name": s.name,
+                "kind": s.kind.as_str_name().to_lowercase(),
+                "line_number": s.line_number,
+            })
+        })
+        .collect();
+    let line_matches: Vec<_> = f
+        .line_matches
+        .iter()
+        .map(|l| {
+            let highlights: Vec<_> = l.highlights.iter().map(|r| [r.start, r.end]).collect();
+            json!({
+                "line_number": l.line_number,
+                "line": l.line,
+                "highlights": highlights,
+                "before": l.before,
+                "after": l.after,
+            })
+        })
+        .collect();
+    json!({
+        "filename": f.filename,
+        "type": f.kind.as_str_name().to_lowercase(),
+        "language": f.file_type,
+        "repository": f.repository,
+        "branches": branches,
+        "commit": f.commit.as_ref().map(|c| json!({
+            "id": c.id,
+            "author": c.author,
+            "time": c.time,
+            "subject": c.subject,
+        })),
+        "url": f.url,
+        "score": f.score,
+        "symbols": symbols,
+        "line_matches": line_matches,
+    })
+}
+
+/// Prints every matching file once, such as a file found on several branches.
+fn print_files(out: &mut impl Write, result: &SearchResult) -> io::Result<()> {
+    let mut printed = BTreeSet::new();
+    for f in result.files.iter() {
+        if printed.insert(f.filename.as_str()) {
+            writeln!(out, "{}", f.filename)?;
+        }
+    }
+    Ok(())
+}
+
+/// Formats seconds since the Unix epoch as a `YYYY-MM-DD` date in UTC.
+fn format_date(time: i64) -> String {
+    // Years start in March, so that the leap day ends them.
+    let days = time.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = (month_from_march + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn stats(loc: &str) -> Result<(), String> {
+    let stats = open_index(loc)?.stats();
+    let mut out = BufWriter::new(io::stdout().lock());
+    let printed = (|| {
+        writeln!(out, "files: {}", stats.files)?;
+        writeln!(out, "content: {} bytes", stats.content_bytes)?;
+        writeln!(out, "skipped files: {}", stats.skipped_files)?;
+        writeln!(out, "commits: {}", stats.commits)?;
+        writeln!(out, "diffs: {}", stats.diffs)?;
+        for (title, counts) in [
+            ("languages", &stats.languages),
+            ("repositories", &stats.repositories),
+            ("branches", &stats.branches),
+        ] {
+            print_counts(&mut out, title, counts)?;
+        }
+        out.flush()
+    })();
+    finish(printed)
+}
+
+/// Prints the counts, largest first, unless there are none.
+fn print_counts(
+    out: &mut impl Write,
+    title: &str,
+    counts: &BTreeMap<String, usize>,
+) -> io::Result<()> {
+    if counts.is_empty() {
+        return Ok(());
+    }
+    let mut counts: Vec<_> = counts.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    writeln!(out, "{}:", title)?;
+    for (name, count) in counts {
+        writeln!(out, "    {}: {}", name, count)?;
+    }
+    Ok(())
+}
+
+/// Lists the indexed files, the ones whose path contains the fragment if
+/// given. Returns whether there were any.
+fn list_files(loc: &str, fragment: Option<&str>) -> Result<bool, String> {
+    let files = open_index(loc)?.find_files(fragment.unwrap_or_default());
+    let mut out = BufWriter::new(io::stdout().lock());
+    let printed = files
+        .iter()
+        .try_for_each(|file| writeln!(out, "{}", file))
+        .and_then(|_| out.flush());
+    finish(printed)?;
+    Ok(!files.is_empty())
+}
+
+/// Runs the command, returning whether anything matched. Commands that don't
+/// search always match.
+fn run(args: &[String]) -> Result<bool, String> {
+    let usage = |err: String| format!("{}\n\n{}", err, USAGE);
+    match (args.first().map(String::as_str), &args[1.min(args.len())..]) {
+        (Some("index"), args) => index(&parse_index_args(args).map_err(usage)?).map(|_| true),
+        (Some("search"), args) => search(&parse_search_args(args).map_err(usage)?),
+        (Some("stats"), [loc]) => stats(loc).map(|_| true),
+        (Some("list-files"), [loc]) => list_files(loc, None),
+        (Some("list-files"), [loc, fragment]) => list_files(loc, Some(fragment)),
+        (Some("help" | "-h" | "--help"), _) => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+        (Some(command @ ("stats" | "list-files")), _) => {
+            Err(usage(format!("unexpected arguments of {}", command)))
+        }
+        (Some(command), _) => Err(usage(format!("unknown command {}", command))),
+        (None, _) => Err(USAGE.to_string()),
     }
-    log::info!("searching done");
 }
 
 fn main() {
     env_logger::init();
-    // index_and_search();
-    search_only();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    process::exit(match run(&args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            eprintln!("{}", err);
+            2
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_highlight() {
+        let marked = |text: &str| format!("{}{}{}", HIGHLIGHT_START, text, HIGHLIGHT_END);
+        assert_eq!(highlight("err = err;", &[0..3, 6..9], false), "err = err;");
+        assert_eq!(
+            highlight("err = err;", &[0..3, 6..9], true),
+            format!("{} = {};", marked("err"), marked("err"))
+        );
+        // Past the end of the line, overlapping and inside a character.
+        assert_eq!(
+            highlight("err\u{e9}", &[0..3, 2..4, 4..5, 3..9], true),
+            format!("{}{}", marked("err"), marked("\u{e9}"))
+        );
+    }
 }
@@ -22,20 +22,24 @@ where
     V: Clone + From<Vec<u8>> + Into<Vec<u8>>,
 {
     pub fn find(&self, key: &str) -> Option<V> {
+        // The newest generation holding the key wins, even if it is a tombstone.
         let find_from_disk = || {
-            (0..=self.data_gen).rev().find_map(|data_gen| {
-                self.index_file(data_gen)
-                    .find_index(key)
-                    .and_then(|index_entry| {
-                        self.fetch(index_entry.data_gen, index_entry.offset)
-                            .filter(|(_key, _)| _key == key)
-                            .map(|(_, value)| value.clone())
-                    })
-            })
+            (0..=self.data_gen)
+                .rev()
+                .find_map(|data_gen| {
+                    self.index_file(data_gen)
+                        .find_index(key)
+                        .and_then(|index_entry| {
+                            self.fetch(index_entry.data_gen, index_entry.offset)
+                                .filter(|(_key, _)| _key == key)
+                                .map(|(_, value)| value)
+                        })
+                })
+                .flatten()
         };
         match self.flushing.as_ref() {
             Some(mem_entries) => match mem_entries.get(key) {
-                Some(value) => Some(value.clone()),
+                Some(value) => value.clone(),
                 None => find_from_disk(),
             },
             None => find_from_disk(),
@@ -121,7 +125,7 @@ where
         IndexFile::of(data_gen, &self.dir_name)
     }
 
-    fn fetch(&self, data_gen: DataGen, offset: Offset) -> Option<(String, V)> {
+    fn fetch(&self, data_gen: DataGen, offset: Offset) -> Option<(String, Option<V>)> {
         let entry = self.with_data_file(data_gen, |df| df.read_entry(offset));
         entry.map(|entry| (entry.key, entry.value))
     }
@@ -19,11 +19,14 @@ pub(crate) struct DataEntry<V> {
     pub key_len: usize,
     pub value_len: usize,
     pub key: String,
-    pub value: V,
+    /// `None` for tombstones.
+    pub value: Option<V>,
 }
 
 impl<V> DataFile<V> {
     pub const FILE_NAME_PREFIX: &'static str = "data";
+    /// Value length marking a tombstone, which has no value data.
+    const TOMBSTONE: usize = u32::MAX as usize;
 }
 
 impl<V> DataFile<V>
@@ -49,6 +52,7 @@ where
     Data Layout:
     [key length][value length][ key data  ][value data ]\0
     <--4 byte--><--4 byte----><--key_len--><-value_len->
+    Tombstones have their value length set to `u32::MAX` and no value data.
     */
     pub fn read_entry(&self, offset: Offset) -> Option<DataEntry<V>> {
         let mut data = &self.file.underlying;
@@ -80,6 +84,10 @@ where
             )
         });
         let value_len = ByteUtils::as_usize(value_len_bytes);
+        let (value_len, tombstone) = match value_len {
+            Self::TOMBSTONE => (0, true),
+            len => (len, false),
+        };
         let key_data = bytes.get(8..(8 + key_len)).unwrap_or_else(|| {
             panic!(
                 "[key_data] failed to read bytes({:?}) for range({:?})",
@@ -103,7 +111,7 @@ where
             key_len,
             value_len,
             key: ByteUtils::as_string(key_data),
-            value: V::from(value_data.to_vec()),
+            value: (!tombstone).then(|| V::from(value_data.to_vec())),
         })
     }
 
@@ -120,12 +128,16 @@ where
         let mut new_index = BTreeMap::new();
         entries.iter().for_each(|(key, value)| {
             let key_bytes = key.as_bytes();
-            let value_bytes = value.clone().into();
+            let value_bytes: Vec<u8> = value.clone().map_or_else(Vec::new, Into::into);
+            let value_len = match value {
+                Some(_) => value_bytes.len(),
+                None => Self::TOMBSTONE,
+            };
             let size = 4 + 4 + key_bytes.len() + 4 + value_bytes.len();
             let bytes: Vec<u8> = [
                 &ByteUtils::from_usize(size),
                 &ByteUtils::from_usize(key_bytes.len()),
-                &ByteUtils::from_usize(value_bytes.len()),
+                &ByteUtils::from_usize(value_len),
                 key_bytes,
                 &value_bytes,
                 b"\0",
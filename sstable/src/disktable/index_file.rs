@@ -120,10 +120,12 @@ impl IndexFile {
             .find_map(|line| {
                 let line = line.expect("failed to read a line");
 
-                let res: Vec<_> = line.split(Self::INDEX_DELIMITER).collect();
-                let _key = res[0].to_string();
-                let offset = res[1].parse::<Offset>().unwrap();
-                if key <= _key.as_ref() {
+                // Keys may contain the delimiter themselves, offsets never do.
+                let (skip_key, offset) = line
+                    .rsplit_once(Self::INDEX_DELIMITER)
+                    .expect("invalid skip index entry");
+                let offset = offset.parse::<Offset>().unwrap();
+                if key <= skip_key {
                     Some(last_offset)
                 } else {
                     last_offset = offset;
@@ -2,51 +2,76 @@ use std::io;
 mod disktable;
 mod memtable;
 mod rich_file;
+mod wal;
 
 pub struct SSTable<V> {
     memtable: memtable::Memtable<V>,
     disktable: disktable::Disktable<V>,
+    wal: wal::Wal,
 }
 
 impl<V> SSTable<V>
 where
     V: Clone + From<Vec<u8>> + Into<Vec<u8>>,
 {
-    pub fn new(dir_name: &str, mem_max_entry: usize) -> SSTable<V> {
-        std::fs::create_dir_all(dir_name)
-            .unwrap_or_else(|_| panic!("failed to create directory {}", dir_name));
-        SSTable {
+    /// Opens the table in the directory, recovering the entries that weren't
+    /// flushed from the write-ahead log.
+    pub fn new(dir_name: &str, mem_max_entry: usize) -> io::Result<SSTable<V>> {
+        std::fs::create_dir_all(dir_name)?;
+        let mut sstable = SSTable {
             memtable: memtable::Memtable::new(mem_max_entry),
-            disktable: disktable::Disktable::new(dir_name).unwrap(),
+            disktable: disktable::Disktable::new(dir_name)?,
+            wal: wal::Wal::open(dir_name)?,
+        };
+
+        let entries = sstable.wal.replay()?;
+        for (key, value) in entries {
+            match value {
+                Some(value) => sstable
+                    .memtable
+                    .set(&key, V::from(value))
+                    .on_flush(|mem| sstable.disktable.flush(mem))?,
+                None => sstable.memtable.delete(&key),
+            }
         }
+        Ok(sstable)
     }
 
     pub fn get(&self, key: &str) -> Option<V> {
-        self.memtable.get(key).cloned()
-        // match self.memtable.get(key) {
-        //     Some(value) => Some(value.clone()),
-        //     None => self.disktable.find(key),
-        // }
+        match self.memtable.get(key) {
+            Some(value) => value.clone(),
+            None => self.disktable.find(key),
+        }
     }
 
     pub fn insert(&mut self, key: &str, value: V) -> Result<(), io::Error> {
-        self.memtable
-            .set(key, value)
-            .on_flush(|mem| self.disktable.flush(mem))
+        self.wal.set(key, &value.clone().into())?;
+        let mut flushed = false;
+        self.memtable.set(key, value).on_flush(|mem| {
+            flushed = true;
+            self.disktable.flush(mem)
+        })?;
+        if flushed {
+            self.wal.clear()?;
+        }
+        Ok(())
     }
 
-    pub fn delete(&mut self, key: &str) {
+    pub fn delete(&mut self, key: &str) -> Result<(), io::Error> {
+        self.wal.delete(key)?;
         self.memtable.delete(key);
+        Ok(())
     }
 
     pub fn clear(&mut self) -> Result<(), io::Error> {
         self.disktable.clear()?;
         self.memtable.clear();
-        Ok(())
+        self.wal.clear()
     }
 
     pub fn flush(&mut self) -> Result<(), io::Error> {
-        self.disktable.flush(self.memtable.flush())
+        self.disktable.flush(self.memtable.flush())?;
+        self.wal.clear()
     }
 }
 
@@ -58,7 +83,7 @@ mod tests {
         let key = |i| format!("key-{}", i);
         let value = |i| format!("value-{}", i).into_bytes();
 
-        let mut sst = SSTable::new("./test_tmp", 200);
+        let mut sst = SSTable::new("./test_tmp", 200).expect("success");
         assert!(sst.clear().is_ok());
         // get -> set -> get
         (1..300).for_each(|i| {
@@ -69,7 +94,7 @@ mod tests {
         // get -> delete -> get
         (1..300).for_each(|i| {
             assert_eq!(sst.get(&key(i)), Some(value(i)));
-            sst.delete(&key(i));
+            sst.delete(&key(i)).expect("success");
             assert_eq!(sst.get(&key(i)), None);
         });
         // get
@@ -82,19 +107,55 @@ mod tests {
     fn test_sstabl_tombstones() {
         let key = |i| format!("key-{}", i);
         let value = |i| format!("value-{}", i).into_bytes();
-        let mut sst = SSTable::new("./test_tmp2", 3);
+        let mut sst = SSTable::new("./test_tmp2", 3).expect("success");
         assert!(sst.clear().is_ok());
         (1..=5).for_each(|i| {
             sst.insert(&key(i), value(i)).expect("success");
         });
-        sst.delete(&key(2));
+        sst.delete(&key(2)).expect("success");
         // restore WAL
         // memtable: [4, 5], tombstone: [2], disktable: [1, 2, 3]
-        let sst = SSTable::new("./test_tmp2", 3);
+        let sst = SSTable::new("./test_tmp2", 3).expect("success");
         assert_eq!(sst.get(&key(1)), Some(value(1)));
         assert_eq!(sst.get(&key(2)), None);
         assert_eq!(sst.get(&key(3)), Some(value(3)));
         assert_eq!(sst.get(&key(4)), Some(value(4)));
         assert_eq!(sst.get(&key(5)), Some(value(5)));
     }
+
+    #[test]
+    fn test_wal_recovery() {
+        use std::io::Write;
+
+        let key = |i| format!("key-{}", i);
+        let value = |i| format!("value-{}", i).into_bytes();
+        let dir = tempfile::tempdir().unwrap();
+        let dir_name = dir.path().to_str().unwrap();
+        {
+            let mut sst = SSTable::new(dir_name, 100).expect("success");
+            (1..=3).for_each(|i| sst.insert(&key(i), value(i)).expect("success"));
+            sst.delete(&key(2)).expect("success");
+        }
+
+        // A crash in the middle of an append leaves a torn operation behind.
+        let mut wal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join("wal"))
+            .unwrap();
+        wal.write_all(&[1, 5, 0, 0, 0, 9, 0, 0, 0, b'k']).unwrap();
+        {
+            let mut sst = SSTable::new(dir_name, 100).expect("success");
+            assert_eq!(sst.get(&key(1)), Some(value(1)));
+            assert_eq!(sst.get(&key(2)), None);
+            assert_eq!(sst.get(&key(3)), Some(value(3)));
+            sst.insert(&key(4), value(4)).expect("success");
+        }
+
+        // Operations logged after the torn one are recovered as well.
+        let sst = SSTable::<Vec<u8>>::new(dir_name, 100).expect("success");
+        assert_eq!(sst.get(&key(1)), Some(value(1)));
+        assert_eq!(sst.get(&key(2)), None);
+        assert_eq!(sst.get(&key(3)), Some(value(3)));
+        assert_eq!(sst.get(&key(4)), Some(value(4)));
+    }
 }
@@ -1,11 +1,12 @@
 use std::{collections::BTreeMap, io};
 
+/// Entries of a memtable, `None` values being tombstones of deleted keys.
 pub(crate) struct MemtableEntries<V> {
-    pub entries: BTreeMap<String, V>,
+    pub entries: BTreeMap<String, Option<V>>,
 }
 
 impl<V> MemtableEntries<V> {
-    pub fn get(&self, key: &str) -> Option<&V> {
+    pub fn get(&self, key: &str) -> Option<&Option<V>> {
         self.entries.get(key)
     }
 }
@@ -25,7 +26,7 @@ impl<V> MemtableOnFlush<V> {
 
 pub struct Memtable<V> {
     max_entry: usize,
-    underlying: BTreeMap<String, V>,
+    underlying: BTreeMap<String, Option<V>>,
 }
 
 impl<V> Memtable<V> {
@@ -36,12 +37,13 @@ impl<V> Memtable<V> {
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<&V> {
+    /// Returns `Some(None)` for deleted keys, so they aren't looked up on disk.
+    pub fn get(&self, key: &str) -> Option<&Option<V>> {
         self.underlying.get(key)
     }
 
     pub fn set(&mut self, key: &str, value: V) -> MemtableOnFlush<V> {
-        self.underlying.insert(key.into(), value);
+        self.underlying.insert(key.into(), Some(value));
         if self.underlying.len() > self.max_entry {
             log::trace!("flush!");
             MemtableOnFlush {
@@ -52,8 +54,9 @@ impl<V> Memtable<V> {
         }
     }
 
+    /// Replaces the value with a tombstone, which shadows the flushed values.
     pub fn delete(&mut self, key: &str) {
-        self.underlying.remove(key);
+        self.underlying.insert(key.into(), None);
     }
 
     pub fn clear(&mut self) {
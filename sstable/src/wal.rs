@@ -0,0 +1,93 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Logged operation, a key along with its value or `None` for deletes.
+pub(crate) type Operation = (String, Option<Vec<u8>>);
+
+/// Wal is the write-ahead log of the memtable. Every operation is appended to
+/// it before it is applied, so a memtable that wasn't flushed can be recovered.
+pub(crate) struct Wal {
+    file: File,
+}
+
+impl Wal {
+    const FILE_NAME: &'static str = "wal";
+    const SET: u8 = 1;
+    const DELETE: u8 = 0;
+
+    pub fn open(dir_name: &str) -> io::Result<Wal> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(Path::new(dir_name).join(Self::FILE_NAME))?;
+        Ok(Wal { file })
+    }
+
+    /*
+    Wal layout:
+    [operation][key length][value length][ key data  ][value data ]
+    <--1 byte--><--4 byte--><--4 byte----><--key_len--><-value_len->
+    */
+    /// Returns logged operations in order. A
+    /// partially written operation at the end, left there by a crash, is
+    /// dropped from the log so that new operations are appended after the
+    /// last complete one.
+    pub fn replay(&mut self) -> io::Result<Vec<Operation>> {
+        let mut bytes = Vec::new();
+        (&self.file).read_to_end(&mut bytes)?;
+
+        let mut entries = Vec::new();
+        let mut rest = bytes.as_slice();
+        while let Some((entry, len)) = Self::read_entry(rest) {
+            entries.push(entry);
+            rest = &rest[len..];
+        }
+        if !rest.is_empty() {
+            self.file.set_len((bytes.len() - rest.len()) as u64)?;
+        }
+        Ok(entries)
+    }
+
+    /// Reads the operation at the start of the bytes along with its length,
+    /// `None` if it is incomplete.
+    fn read_entry(bytes: &[u8]) -> Option<(Operation, usize)> {
+        let operation = *bytes.first()?;
+        if operation != Self::SET && operation != Self::DELETE {
+            return None;
+        }
+        let key_len = u32::from_le_bytes(bytes.get(1..5)?.try_into().unwrap()) as usize;
+        let value_len = u32::from_le_bytes(bytes.get(5..9)?.try_into().unwrap()) as usize;
+        let len = 9usize.checked_add(key_len)?.checked_add(value_len)?;
+        let entry = bytes.get(9..len)?;
+        let key = String::from_utf8(entry[..key_len].to_vec()).ok()?;
+        let value = (operation == Self::SET).then(|| entry[key_len..].to_vec());
+        Some(((key, value), len))
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.append(Self::SET, key, value)
+    }
+
+    pub fn delete(&mut self, key: &str) -> io::Result<()> {
+        self.append(Self::DELETE, key, &[])
+    }
+
+    /// Drops all logged operations, once the memtable got flushed.
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)
+    }
+
+    fn append(&mut self, operation: u8, key: &str, value: &[u8]) -> io::Result<()> {
+        let bytes: Vec<u8> = [
+            &[operation][..],
+            &(key.len() as u32).to_le_bytes(),
+            &(value.len() as u32).to_le_bytes(),
+            key.as_bytes(),
+            value,
+        ]
+        .concat();
+        self.file.write_all(&bytes)
+    }
+}